@@ -0,0 +1,242 @@
+//! Account-level consumers, for sinks that only need the accounts of a snapshot.
+//!
+//! [`AccountProcessor`] iterates the accounts of each AppendVec, filters and transforms them,
+//! and copies them into batches for an [`AccountConsumer`].
+
+use {
+    crate::{
+        append_vec::{AppendVec, StoredAccountMeta},
+        append_vec_iter,
+        metrics::metrics,
+        parallel::{
+            par_process_append_vecs, AppendVecProcessor, DiscardOutput, ParIterConfig,
+            ParIterOutput, SnapshotInfo,
+        },
+        solana::DeserializableVersionedBank,
+        AppendVecIterator,
+    },
+    solana_sdk::{
+        account::AccountSharedData, clock::Slot, config, feature, native_loader, pubkey::Pubkey,
+        sysvar,
+    },
+    std::{collections::HashSet, mem, sync::Arc},
+};
+
+/// Account copied out of an AppendVec.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Account {
+    /// Slot of the AppendVec the account is stored in.
+    pub slot: Slot,
+    pub write_version: u64,
+    pub pubkey: Pubkey,
+    pub account: AccountSharedData,
+}
+
+impl Account {
+    pub fn new(slot: Slot, account: &StoredAccountMeta) -> Self {
+        Self {
+            slot,
+            write_version: account.meta.write_version_obsolete,
+            pubkey: account.meta.pubkey,
+            account: account.clone_account(),
+        }
+    }
+}
+
+/// Maps an account before it is delivered, or drops it by returning `None`.
+pub type AccountTransform = Arc<dyn Fn(Account) -> Option<Account> + Send + Sync>;
+
+/// Selects the accounts to keep.
+#[derive(Clone, Debug, Default)]
+pub struct AccountFilter {
+    pubkeys: HashSet<Pubkey>,
+    owners: HashSet<Pubkey>,
+    closed: bool,
+}
+
+impl AccountFilter {
+    /// Keeps the accounts with one of `pubkeys` or owned by one of `owners`.
+    pub fn new(
+        pubkeys: impl IntoIterator<Item = Pubkey>,
+        owners: impl IntoIterator<Item = Pubkey>,
+    ) -> Self {
+        Self {
+            pubkeys: pubkeys.into_iter().collect(),
+            owners: owners.into_iter().collect(),
+            closed: false,
+        }
+    }
+
+    /// Also keeps closed accounts, stored with zero lamports and the system program as owner,
+    /// so that consumers tracking the latest version of accounts see them closed.
+    pub const fn with_closed_accounts(mut self) -> Self {
+        self.closed = true;
+        self
+    }
+
+    /// Also keeps the accounts the runtime reads when loading the bank:
+    /// sysvars, native programs, feature and config accounts, and the vote and
+    /// stake accounts of the bank's stakes cache.
+    pub fn with_runtime_accounts(mut self, bank: &DeserializableVersionedBank) -> Self {
+        self.owners.extend([
+            sysvar::id(),
+            native_loader::id(),
+            feature::id(),
+            config::program::id(),
+        ]);
+        self.pubkeys
+            .extend(bank.stakes.vote_accounts.iter().map(|(pubkey, _)| *pubkey));
+        self.pubkeys
+            .extend(bank.stakes.stake_delegations.keys().copied());
+        self
+    }
+
+    pub fn matches(&self, account: &StoredAccountMeta) -> bool {
+        self.pubkeys.contains(&account.meta.pubkey)
+            || self.owners.contains(&account.account_meta.owner)
+            || (self.closed && account.account_meta.lamports == 0)
+    }
+}
+
+/// Selection and batching of the accounts delivered to [`AccountConsumer`]s.
+#[derive(Clone)]
+pub struct AccountOptions {
+    /// Accounts per batch.
+    pub batch_size: usize,
+    /// Only deliver the accounts matching the filter, all of them if not set.
+    pub filter: Option<Arc<AccountFilter>>,
+    /// Applied in order to the accounts matching the filter.
+    pub transforms: Vec<AccountTransform>,
+}
+
+impl AccountOptions {
+    /// Delivers all accounts in batches of `batch_size`.
+    pub const fn new(batch_size: usize) -> Self {
+        Self {
+            batch_size,
+            filter: None,
+            transforms: Vec::new(),
+        }
+    }
+}
+
+/// Consumer of accounts, delivered in batches by an [`AccountProcessor`] on the blocking thread pool.
+///
+/// Every version of an account stored in the snapshot is delivered, in no particular order across
+/// consumers. Hooks are called like those of [`AppendVecProcessor`].
+pub trait AccountConsumer {
+    fn on_start(&mut self, _info: &SnapshotInfo) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn on_accounts(&mut self, accounts: Vec<Account>) -> anyhow::Result<()>;
+
+    fn on_finish(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// Feeds the accounts of AppendVecs to an [`AccountConsumer`] in batches, see [`AccountOptions`].
+/// Batches span AppendVecs, the last one is delivered when the processor finishes.
+///
+/// Counts the accounts read in [`Metrics::accounts`](crate::metrics::Metrics::accounts),
+/// and those not delivered in [`Metrics::filtered_accounts`](crate::metrics::Metrics::filtered_accounts).
+pub struct AccountProcessor<C> {
+    consumer: C,
+    options: AccountOptions,
+    batch: Vec<Account>,
+}
+
+impl<C: AccountConsumer> AccountProcessor<C> {
+    pub const fn new(consumer: C, options: AccountOptions) -> Self {
+        Self {
+            consumer,
+            options,
+            batch: Vec::new(),
+        }
+    }
+
+    pub fn into_inner(self) -> C {
+        self.consumer
+    }
+
+    fn flush(&mut self) -> anyhow::Result<()> {
+        if self.batch.is_empty() {
+            return Ok(());
+        }
+        let batch = mem::take(&mut self.batch);
+        self.consumer.on_accounts(batch)
+    }
+}
+
+impl<C: AccountConsumer> AppendVecProcessor for AccountProcessor<C> {
+    type Output = ();
+
+    fn on_start(&mut self, info: &SnapshotInfo) -> anyhow::Result<()> {
+        self.consumer.on_start(info)
+    }
+
+    fn process(&mut self, append_vec: AppendVec) -> anyhow::Result<()> {
+        let mut count = 0u64;
+        let mut filtered = 0u64;
+        for handle in append_vec_iter(&append_vec) {
+            count += 1;
+            let Some(account) = handle.access() else {
+                continue;
+            };
+            if let Some(filter) = &self.options.filter {
+                if !filter.matches(&account) {
+                    filtered += 1;
+                    continue;
+                }
+            }
+            let account = self.options.transforms.iter().try_fold(
+                Account::new(append_vec.slot(), &account),
+                |account, transform| transform(account),
+            );
+            let Some(account) = account else {
+                filtered += 1;
+                continue;
+            };
+            self.batch.push(account);
+            if self.batch.len() >= self.options.batch_size.max(1) {
+                self.flush()?;
+            }
+        }
+        let metrics = metrics();
+        metrics.accounts.inc_by(count);
+        metrics.filtered_accounts.inc_by(filtered);
+        Ok(())
+    }
+
+    fn on_finish(&mut self) -> anyhow::Result<()> {
+        self.flush()?;
+        self.consumer.on_finish()
+    }
+}
+
+/// Feeds the accounts of AppendVecs to up to `sink_threads` account consumers, see [`AccountProcessor`].
+/// Returns the finished consumers once all accounts were delivered.
+pub async fn par_process_accounts<F, C>(
+    iterator: AppendVecIterator<'_>,
+    info: &SnapshotInfo,
+    create_consumer: F,
+    options: &AccountOptions,
+    config: ParIterConfig,
+) -> anyhow::Result<ParIterOutput<C>>
+where
+    F: Fn() -> C,
+    C: AccountConsumer + Send + 'static,
+{
+    let create_processor = || AccountProcessor::new(create_consumer(), options.clone());
+    let (output, DiscardOutput) =
+        par_process_append_vecs(iterator, info, create_processor, DiscardOutput, config).await?;
+    Ok(ParIterOutput {
+        consumers: output
+            .consumers
+            .into_iter()
+            .map(AccountProcessor::into_inner)
+            .collect(),
+        skipped: output.skipped,
+    })
+}
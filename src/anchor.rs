@@ -0,0 +1,504 @@
+//! Decoding of Anchor program accounts through the program's IDL.
+//!
+//! Both the legacy IDL format (Anchor < 0.30) and the current one are supported.
+//! Accounts are identified by their 8-byte discriminator and decoded with Borsh rules.
+
+use {
+    crate::decode::{
+        AccountDecoder, DataReader, DecodeError, DecodeResult, DecodedAccount, Primitive,
+    },
+    serde_json::{Map, Value},
+    sha2::{Digest, Sha256},
+    solana_sdk::pubkey::Pubkey,
+    std::{collections::HashMap, path::Path, str::FromStr},
+    thiserror::Error,
+};
+
+const DISCRIMINATOR_LEN: usize = 8;
+
+/// Maximum nesting of defined types, guards against recursive type definitions.
+const MAX_DEPTH: usize = 64;
+
+#[derive(Error, Debug)]
+pub enum IdlError {
+    #[error("{0}")]
+    IOError(#[from] std::io::Error),
+    #[error("Failed to parse IDL: {0}")]
+    JsonError(#[from] serde_json::Error),
+    #[error("Invalid IDL: {0}")]
+    Invalid(String),
+}
+
+#[derive(Clone, Debug)]
+enum IdlType {
+    Primitive(Primitive),
+    Bytes,
+    String,
+    Vec(Box<IdlType>),
+    Option(Box<IdlType>),
+    COption(Box<IdlType>),
+    Array(Box<IdlType>, usize),
+    Defined(String),
+}
+
+#[derive(Clone, Debug)]
+enum IdlFields {
+    Named(Vec<(String, IdlType)>),
+    Tuple(Vec<IdlType>),
+}
+
+#[derive(Clone, Debug)]
+enum IdlTypeDefTy {
+    Struct(IdlFields),
+    Enum(Vec<(String, IdlFields)>),
+    Alias(IdlType),
+}
+
+#[derive(Clone, Debug)]
+struct IdlTypeDef {
+    ty: IdlTypeDefTy,
+    /// Set if the type uses a memory layout Borsh decoding cannot handle.
+    unsupported: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+struct IdlAccount {
+    name: String,
+    discriminator: [u8; DISCRIMINATOR_LEN],
+}
+
+/// Decodes the accounts of an Anchor program.
+pub struct AnchorDecoder {
+    program_id: Pubkey,
+    accounts: Vec<IdlAccount>,
+    types: HashMap<String, IdlTypeDef>,
+}
+
+impl AnchorDecoder {
+    pub fn from_file(path: &Path) -> Result<Self, IdlError> {
+        let idl: Value = serde_json::from_reader(std::fs::File::open(path)?)?;
+        Self::from_idl(&idl)
+    }
+
+    pub fn from_idl(idl: &Value) -> Result<Self, IdlError> {
+        let address = idl
+            .get("address")
+            .or_else(|| idl.get("metadata").and_then(|m| m.get("address")))
+            .and_then(Value::as_str)
+            .ok_or_else(|| IdlError::Invalid("missing program address".to_owned()))?;
+        let program_id = Pubkey::from_str(address)
+            .map_err(|err| IdlError::Invalid(format!("program address {address}: {err}")))?;
+
+        let mut types = HashMap::new();
+        for type_def in array_field(idl, "types")? {
+            let name = str_field(type_def, "name")?;
+            types.insert(name.to_owned(), parse_type_def(type_def)?);
+        }
+
+        let mut accounts = Vec::new();
+        for account in array_field(idl, "accounts")? {
+            let name = str_field(account, "name")?;
+            // Legacy IDLs define the account layout inline.
+            if account.get("type").is_some() {
+                types.insert(name.to_owned(), parse_type_def(account)?);
+            }
+            if !types.contains_key(name) {
+                return Err(IdlError::Invalid(format!("missing type of account {name}")));
+            }
+            let discriminator = match account.get("discriminator") {
+                Some(discriminator) => serde_json::from_value(discriminator.clone())?,
+                None => account_discriminator(name),
+            };
+            accounts.push(IdlAccount {
+                name: name.to_owned(),
+                discriminator,
+            });
+        }
+
+        Ok(Self {
+            program_id,
+            accounts,
+            types,
+        })
+    }
+
+    fn decode_type(
+        &self,
+        ty: &IdlType,
+        reader: &mut DataReader,
+        depth: usize,
+    ) -> DecodeResult<Value> {
+        Ok(match ty {
+            IdlType::Primitive(primitive) => reader.read_primitive(*primitive)?,
+            IdlType::Bytes => {
+                let len = reader.read_u32()? as usize;
+                const_hex::encode(reader.read_bytes(len)?).into()
+            }
+            IdlType::String => {
+                let offset = reader.offset();
+                let len = reader.read_u32()? as usize;
+                std::str::from_utf8(reader.read_bytes(len)?)
+                    .map_err(|_| DecodeError::InvalidValue {
+                        kind: "string",
+                        offset,
+                    })?
+                    .into()
+            }
+            IdlType::Vec(inner) => {
+                let offset = reader.offset();
+                let len = reader.read_u32()? as usize;
+                if len > reader.remaining() {
+                    return Err(DecodeError::InvalidValue {
+                        kind: "vec length",
+                        offset,
+                    });
+                }
+                (0..len)
+                    .map(|_| self.decode_type(inner, reader, depth))
+                    .collect::<DecodeResult<Vec<_>>>()?
+                    .into()
+            }
+            IdlType::Option(inner) => {
+                if reader.read_bool()? {
+                    self.decode_type(inner, reader, depth)?
+                } else {
+                    Value::Null
+                }
+            }
+            IdlType::COption(inner) => {
+                let some = reader.read_coption_tag()?;
+                let value = self.decode_type(inner, reader, depth)?;
+                if some {
+                    value
+                } else {
+                    Value::Null
+                }
+            }
+            IdlType::Array(inner, len) => {
+                // Bounds arrays of zero-size types, which would not consume any data.
+                if *len > reader.remaining() {
+                    return Err(DecodeError::InvalidValue {
+                        kind: "array length",
+                        offset: reader.offset(),
+                    });
+                }
+                (0..*len)
+                    .map(|_| self.decode_type(inner, reader, depth))
+                    .collect::<DecodeResult<Vec<_>>>()?
+                    .into()
+            }
+            IdlType::Defined(name) => self.decode_defined(name, reader, depth + 1)?,
+        })
+    }
+
+    fn decode_defined(
+        &self,
+        name: &str,
+        reader: &mut DataReader,
+        depth: usize,
+    ) -> DecodeResult<Value> {
+        if depth > MAX_DEPTH {
+            return Err(DecodeError::Unsupported(format!(
+                "type {name} is nested too deeply"
+            )));
+        }
+        let type_def = self
+            .types
+            .get(name)
+            .ok_or_else(|| DecodeError::Unsupported(format!("undefined type {name}")))?;
+        if let Some(reason) = &type_def.unsupported {
+            return Err(DecodeError::Unsupported(format!("type {name}: {reason}")));
+        }
+        match &type_def.ty {
+            IdlTypeDefTy::Struct(fields) => self.decode_fields(fields, reader, depth),
+            IdlTypeDefTy::Enum(variants) => {
+                let offset = reader.offset();
+                let index = reader.read_u8()? as usize;
+                let (variant, fields) = variants.get(index).ok_or(DecodeError::InvalidValue {
+                    kind: "enum variant",
+                    offset,
+                })?;
+                Ok(match fields {
+                    IdlFields::Tuple(types) if types.is_empty() => variant.clone().into(),
+                    fields => {
+                        let mut map = Map::new();
+                        map.insert(variant.clone(), self.decode_fields(fields, reader, depth)?);
+                        map.into()
+                    }
+                })
+            }
+            IdlTypeDefTy::Alias(ty) => self.decode_type(ty, reader, depth),
+        }
+    }
+
+    fn decode_fields(
+        &self,
+        fields: &IdlFields,
+        reader: &mut DataReader,
+        depth: usize,
+    ) -> DecodeResult<Value> {
+        Ok(match fields {
+            IdlFields::Named(fields) => fields
+                .iter()
+                .map(|(name, ty)| Ok((name.clone(), self.decode_type(ty, reader, depth)?)))
+                .collect::<DecodeResult<Map<_, _>>>()?
+                .into(),
+            IdlFields::Tuple(types) => types
+                .iter()
+                .map(|ty| self.decode_type(ty, reader, depth))
+                .collect::<DecodeResult<Vec<_>>>()?
+                .into(),
+        })
+    }
+}
+
+impl AccountDecoder for AnchorDecoder {
+    fn program_id(&self) -> Pubkey {
+        self.program_id
+    }
+
+    fn decode(&self, data: &[u8]) -> Option<DecodeResult<DecodedAccount>> {
+        let discriminator = data.get(..DISCRIMINATOR_LEN)?;
+        let account = self
+            .accounts
+            .iter()
+            .find(|account| account.discriminator == discriminator)?;
+        let mut reader = DataReader::new(data, DISCRIMINATOR_LEN);
+        Some(
+            self.decode_defined(&account.name, &mut reader, 0)
+                .map(|value| DecodedAccount {
+                    type_name: account.name.clone(),
+                    value,
+                }),
+        )
+    }
+}
+
+/// Discriminator of accounts in legacy IDLs, which do not list it.
+fn account_discriminator(name: &str) -> [u8; DISCRIMINATOR_LEN] {
+    let hash = Sha256::digest(format!("account:{name}"));
+    hash[..DISCRIMINATOR_LEN]
+        .try_into()
+        .expect("hash is longer")
+}
+
+fn array_field<'a>(value: &'a Value, field: &str) -> Result<&'a [Value], IdlError> {
+    match value.get(field) {
+        None => Ok(&[]),
+        Some(Value::Array(values)) => Ok(values),
+        Some(_) => Err(IdlError::Invalid(format!("{field} is not an array"))),
+    }
+}
+
+fn str_field<'a>(value: &'a Value, field: &str) -> Result<&'a str, IdlError> {
+    value
+        .get(field)
+        .and_then(Value::as_str)
+        .ok_or_else(|| IdlError::Invalid(format!("missing {field} in {value}")))
+}
+
+fn parse_type_def(type_def: &Value) -> Result<IdlTypeDef, IdlError> {
+    let ty = type_def
+        .get("type")
+        .ok_or_else(|| IdlError::Invalid(format!("missing type in {type_def}")))?;
+    let parsed = match str_field(ty, "kind")? {
+        "struct" => IdlTypeDefTy::Struct(parse_fields(ty.get("fields"))?),
+        "enum" => IdlTypeDefTy::Enum(
+            array_field(ty, "variants")?
+                .iter()
+                .map(|variant| {
+                    Ok((
+                        str_field(variant, "name")?.to_owned(),
+                        parse_fields(variant.get("fields"))?,
+                    ))
+                })
+                .collect::<Result<_, IdlError>>()?,
+        ),
+        "type" => IdlTypeDefTy::Alias(parse_type(
+            ty.get("alias")
+                .ok_or_else(|| IdlError::Invalid(format!("missing alias in {ty}")))?,
+        )?),
+        kind => return Err(IdlError::Invalid(format!("unknown type kind {kind}"))),
+    };
+
+    // Zero-copy types are laid out in memory, which only matches Borsh when packed.
+    let serialization = type_def
+        .get("serialization")
+        .and_then(Value::as_str)
+        .unwrap_or("borsh");
+    let packed = type_def
+        .get("repr")
+        .and_then(|repr| repr.get("packed"))
+        .and_then(Value::as_bool)
+        .unwrap_or(false);
+    let unsupported = (serialization != "borsh" && !packed)
+        .then(|| format!("{serialization} serialization without packed representation"));
+
+    Ok(IdlTypeDef {
+        ty: parsed,
+        unsupported,
+    })
+}
+
+fn parse_fields(fields: Option<&Value>) -> Result<IdlFields, IdlError> {
+    let fields = match fields {
+        None => return Ok(IdlFields::Tuple(vec![])),
+        Some(Value::Array(fields)) => fields,
+        Some(fields) => return Err(IdlError::Invalid(format!("invalid fields {fields}"))),
+    };
+    let named = fields
+        .first()
+        .map(|field| field.get("name").is_some())
+        .unwrap_or(false);
+    Ok(if named {
+        IdlFields::Named(
+            fields
+                .iter()
+                .map(|field| {
+                    let ty = field
+                        .get("type")
+                        .ok_or_else(|| IdlError::Invalid(format!("missing type in {field}")))?;
+                    Ok((str_field(field, "name")?.to_owned(), parse_type(ty)?))
+                })
+                .collect::<Result<_, IdlError>>()?,
+        )
+    } else {
+        IdlFields::Tuple(fields.iter().map(parse_type).collect::<Result<_, _>>()?)
+    })
+}
+
+fn parse_type(ty: &Value) -> Result<IdlType, IdlError> {
+    let invalid = || IdlError::Invalid(format!("unsupported type {ty}"));
+    match ty {
+        Value::String(name) => match name.as_str() {
+            "bytes" => Ok(IdlType::Bytes),
+            "string" => Ok(IdlType::String),
+            name => Primitive::from_name(name)
+                .map(IdlType::Primitive)
+                .ok_or_else(invalid),
+        },
+        Value::Object(object) => {
+            if let Some(inner) = object.get("vec") {
+                Ok(IdlType::Vec(Box::new(parse_type(inner)?)))
+            } else if let Some(inner) = object.get("option") {
+                Ok(IdlType::Option(Box::new(parse_type(inner)?)))
+            } else if let Some(inner) = object.get("coption") {
+                Ok(IdlType::COption(Box::new(parse_type(inner)?)))
+            } else if let Some(Value::Array(array)) = object.get("array") {
+                match array.as_slice() {
+                    [inner, len] => Ok(IdlType::Array(
+                        Box::new(parse_type(inner)?),
+                        len.as_u64().ok_or_else(invalid)? as usize,
+                    )),
+                    _ => Err(invalid()),
+                }
+            } else if let Some(defined) = object.get("defined") {
+                // Legacy IDLs reference types by name, current ones by `{ "name": .. }`.
+                let name = match defined {
+                    Value::String(name) => name,
+                    defined => defined
+                        .get("name")
+                        .and_then(Value::as_str)
+                        .ok_or_else(invalid)?,
+                };
+                Ok(IdlType::Defined(name.to_owned()))
+            } else {
+                Err(invalid())
+            }
+        }
+        _ => Err(invalid()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, serde_json::json};
+
+    const PROGRAM_ID: &str = "Fg6PaFpoGXkYsidMpWTK6W2BeZ7FEfcYkg476zPFsLnS";
+
+    fn decoder() -> AnchorDecoder {
+        AnchorDecoder::from_idl(&json!({
+            "address": PROGRAM_ID,
+            "accounts": [{ "name": "Counter", "discriminator": [1, 2, 3, 4, 5, 6, 7, 8] }],
+            "types": [{
+                "name": "Counter",
+                "type": {
+                    "kind": "struct",
+                    "fields": [
+                        { "name": "count", "type": "u32" },
+                        { "name": "label", "type": "string" },
+                    ],
+                },
+            }],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn decodes_matching_discriminator() {
+        let mut data = vec![1, 2, 3, 4, 5, 6, 7, 8];
+        data.extend_from_slice(&42u32.to_le_bytes());
+        data.extend_from_slice(&2u32.to_le_bytes());
+        data.extend_from_slice(b"hi");
+
+        let decoded = decoder().decode(&data).unwrap().unwrap();
+        assert_eq!(decoded.type_name, "Counter");
+        assert_eq!(decoded.value, json!({ "count": 42, "label": "hi" }));
+    }
+
+    #[test]
+    fn legacy_discriminator_is_derived_from_the_name() {
+        let decoder = AnchorDecoder::from_idl(&json!({
+            "metadata": { "address": PROGRAM_ID },
+            "accounts": [{
+                "name": "Counter",
+                "type": { "kind": "struct", "fields": [{ "name": "count", "type": "u8" }] },
+            }],
+        }))
+        .unwrap();
+        let mut data = account_discriminator("Counter").to_vec();
+        data.push(7);
+
+        let decoded = decoder.decode(&data).unwrap().unwrap();
+        assert_eq!(decoded.value, json!({ "count": 7 }));
+    }
+
+    #[test]
+    fn unknown_discriminator_is_not_decoded() {
+        let decoder = decoder();
+        assert!(decoder
+            .decode(&[8, 7, 6, 5, 4, 3, 2, 1, 42, 0, 0, 0])
+            .is_none());
+        assert!(decoder.decode(&[1, 2, 3]).is_none());
+    }
+
+    #[test]
+    fn array_longer_than_data_is_rejected() {
+        let decoder = AnchorDecoder::from_idl(&json!({
+            "address": PROGRAM_ID,
+            "accounts": [{ "name": "Units", "discriminator": [1, 2, 3, 4, 5, 6, 7, 8] }],
+            "types": [
+                { "name": "Unit", "type": { "kind": "struct" } },
+                {
+                    "name": "Units",
+                    "type": {
+                        "kind": "struct",
+                        "fields": [{
+                            "name": "units",
+                            "type": { "array": [{ "defined": "Unit" }, u32::MAX] },
+                        }],
+                    },
+                },
+            ],
+        }))
+        .unwrap();
+        let result = decoder.decode(&[1, 2, 3, 4, 5, 6, 7, 8]).unwrap();
+        assert!(matches!(result, Err(DecodeError::InvalidValue { .. })));
+    }
+
+    #[test]
+    fn truncated_account_fails_to_decode() {
+        let result = decoder().decode(&[1, 2, 3, 4, 5, 6, 7, 8, 42]).unwrap();
+        assert!(matches!(result, Err(DecodeError::UnexpectedEof { .. })));
+    }
+}
@@ -16,6 +16,7 @@
 // Source: solana/runtime/src/append_vec.rs
 
 use {
+    crate::hot_storage::HotStorage,
     log::*,
     memmap2::{Mmap, MmapMut},
     solana_accounts_db::{
@@ -25,29 +26,37 @@ use {
         u64_align,
     },
     solana_sdk::{
-        account::{Account, AccountSharedData},
+        account::{Account, AccountSharedData, ReadableAccount},
         hash::Hash,
+        pubkey::Pubkey,
+        system_instruction::MAX_PERMITTED_DATA_LENGTH,
     },
     std::{
+        borrow::Cow,
         convert::TryFrom,
         fs::OpenOptions,
         io::{self, Read},
         mem,
         path::Path,
+        str::FromStr,
     },
 };
 
 /// References to account data stored elsewhere. Getting an `Account` requires cloning
 /// (see `StoredAccountMeta::clone_account()`).
+///
+/// Accounts of hot storage files don't have the AppendVec metadata layout, their
+/// metadata is decoded into owned values.
 #[derive(PartialEq, Eq, Debug)]
 pub struct StoredAccountMeta<'a> {
-    pub meta: &'a StoredMeta,
+    pub meta: Cow<'a, StoredMeta>,
     /// account data
-    pub account_meta: &'a AccountMeta,
+    pub account_meta: Cow<'a, AccountMeta>,
     pub data: &'a [u8],
+    /// Byte offset of the account in an AppendVec, or its index in a hot storage file.
     pub offset: usize,
     pub stored_size: usize,
-    pub hash: &'a Hash,
+    pub hash: Cow<'a, Hash>,
 }
 
 impl<'a> StoredAccountMeta<'a> {
@@ -61,6 +70,68 @@ impl<'a> StoredAccountMeta<'a> {
             data: self.data.to_vec(),
         })
     }
+
+    /// Encodes the account in the AppendVec layout, without trailing alignment padding.
+    pub fn to_append_vec_bytes(&self) -> Vec<u8> {
+        let meta_len = mem::size_of::<StoredMeta>() + mem::size_of::<AccountMeta>();
+        let mut bytes = Vec::with_capacity(meta_len + mem::size_of::<Hash>() + self.data.len());
+        bytes.extend_from_slice(&self.meta.write_version_obsolete.to_le_bytes());
+        bytes.extend_from_slice(&self.meta.data_len.to_le_bytes());
+        bytes.extend_from_slice(self.meta.pubkey.as_ref());
+        bytes.extend_from_slice(&self.account_meta.lamports.to_le_bytes());
+        bytes.extend_from_slice(&self.account_meta.rent_epoch.to_le_bytes());
+        bytes.extend_from_slice(self.account_meta.owner.as_ref());
+        bytes.push(self.account_meta.executable as u8);
+        bytes.resize(meta_len, 0);
+        bytes.extend_from_slice(&self.hash.to_bytes());
+        bytes.extend_from_slice(self.data);
+        bytes
+    }
+}
+
+impl<'a> ReadableAccount for StoredAccountMeta<'a> {
+    fn lamports(&self) -> u64 {
+        self.account_meta.lamports
+    }
+    fn data(&self) -> &[u8] {
+        self.data
+    }
+    fn owner(&self) -> &Pubkey {
+        &self.account_meta.owner
+    }
+    fn executable(&self) -> bool {
+        self.account_meta.executable
+    }
+    fn rent_epoch(&self) -> u64 {
+        self.account_meta.rent_epoch
+    }
+}
+
+/// Layout of the accounts in an account storage file.
+///
+/// Snapshot manifests and file names don't record the format of storage files, it is
+/// detected from the magic number ending the footer of hot storage files.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AccountsFileFormat {
+    /// `StoredMeta`, `AccountMeta`, `Hash` and data of each account, 8-byte aligned.
+    #[default]
+    AppendVec,
+    /// Hot storage file of the tiered storage format.
+    Hot,
+}
+
+impl FromStr for AccountsFileFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "append-vec" => Ok(Self::AppendVec),
+            "hot" => Ok(Self::Hot),
+            _ => Err(format!(
+                "unknown storage format {s}, expected append-vec or hot"
+            )),
+        }
+    }
 }
 
 /// A thread-safe, file-backed block of memory used to store `Account` instances. Append operations
@@ -78,6 +149,11 @@ pub struct AppendVec {
     file_size: u64,
 
     slot: u64,
+
+    id: u64,
+
+    /// Set if the file is in the hot storage format rather than the AppendVec layout.
+    hot: Option<HotStorage>,
 }
 
 impl AppendVec {
@@ -126,6 +202,8 @@ impl AppendVec {
         path: P,
         current_len: usize,
         slot: u64,
+        id: u64,
+        format: Option<AccountsFileFormat>,
     ) -> io::Result<Self> {
         let data = OpenOptions::new()
             .read(true)
@@ -145,28 +223,40 @@ impl AppendVec {
             result?
         };
 
+        let hot = HotStorage::open(&map, format)?;
         let new = AppendVec {
             map,
             current_len,
             file_size,
             slot,
+            id,
+            hot,
         };
 
         Ok(new)
     }
 
+    /// Reads the first `current_len` bytes of an AppendVec file of `file_size` bytes.
     pub fn new_from_reader<R: Read>(
         reader: &mut R,
         current_len: usize,
+        file_size: u64,
         slot: u64,
+        id: u64,
+        format: Option<AccountsFileFormat>,
     ) -> io::Result<Self> {
+        AppendVec::sanitize_len_and_size(current_len, file_size as usize)?;
         let mut map = MmapMut::map_anon(current_len)?;
         io::copy(&mut reader.take(current_len as u64), &mut map.as_mut())?;
+        let map = map.make_read_only()?;
+        let hot = HotStorage::open(&map, format)?;
         Ok(AppendVec {
-            map: map.make_read_only()?,
+            map,
             current_len,
-            file_size: current_len as u64,
+            file_size,
             slot,
+            id,
+            hot,
         })
     }
 
@@ -204,7 +294,13 @@ impl AppendVec {
     /// Return account metadata for the account at `offset` if its data doesn't overrun
     /// the internal buffer. Otherwise return None. Also return the offset of the first byte
     /// after the requested data that falls on a 64-byte boundary.
+    ///
+    /// For hot storage files, `offset` is the index of the account and the index of the
+    /// next account is returned.
     pub fn get_account<'a>(&'a self, offset: usize) -> Option<(StoredAccountMeta<'a>, usize)> {
+        if let Some(hot) = &self.hot {
+            return hot.get_account(&self.map, offset);
+        }
         let (meta, next): (&'a StoredMeta, _) = self.get_type(offset)?;
         let (account_meta, next): (&'a AccountMeta, _) = self.get_type(next)?;
         let (hash, next): (&'a Hash, _) = self.get_type(next)?;
@@ -212,18 +308,105 @@ impl AppendVec {
         let stored_size = next - offset;
         Some((
             StoredAccountMeta {
-                meta,
-                account_meta,
+                meta: Cow::Borrowed(meta),
+                account_meta: Cow::Borrowed(account_meta),
                 data,
                 offset,
                 stored_size,
-                hash,
+                hash: Cow::Borrowed(hash),
             },
             next,
         ))
     }
 
+    /// Returns the stored bytes of an account of this AppendVec, without trailing alignment padding.
+    /// Accounts of hot storage files are encoded in the AppendVec layout.
+    pub fn account_bytes(&self, account: &StoredAccountMeta) -> Cow<'_, [u8]> {
+        if self.hot.is_some() {
+            return Cow::Owned(account.to_append_vec_bytes());
+        }
+        let len = mem::size_of::<StoredMeta>()
+            + mem::size_of::<AccountMeta>()
+            + mem::size_of::<Hash>()
+            + account.data.len();
+        Cow::Borrowed(&self.map[account.offset..account.offset + len])
+    }
+
+    /// Checks that every account parses, that data lengths are within the runtime limit
+    /// and, for AppendVecs, that the last account ends at `current_len`, up to alignment.
+    /// Returns the number of accounts.
+    pub fn verify_accounts(&self) -> io::Result<u64> {
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+        let check_data_len = |account: &StoredAccountMeta| {
+            if account.meta.data_len > MAX_PERMITTED_DATA_LENGTH {
+                return Err(invalid(format!(
+                    "account {} at {} has data length {} over the {} limit",
+                    account.meta.pubkey,
+                    account.offset,
+                    account.meta.data_len,
+                    MAX_PERMITTED_DATA_LENGTH
+                )));
+            }
+            Ok(())
+        };
+
+        if let Some(hot) = &self.hot {
+            for index in 0..hot.num_accounts() {
+                let (account, _) = hot
+                    .get_account(&self.map, index)
+                    .ok_or_else(|| invalid(format!("account {index} does not parse")))?;
+                check_data_len(&account)?;
+            }
+            return Ok(hot.num_accounts() as u64);
+        }
+
+        let mut accounts = 0;
+        let mut offset = 0;
+        let mut end = 0;
+        while offset < self.current_len {
+            let (account, next) = self
+                .get_account(offset)
+                .ok_or_else(|| invalid(format!("account at offset {offset} does not parse")))?;
+            check_data_len(&account)?;
+            end = offset + self.account_bytes(&account).len();
+            offset = next;
+            accounts += 1;
+        }
+        // Like accounts-db, accept a `current_len` that includes the alignment after the last account.
+        if u64_align!(end) != u64_align!(self.current_len) {
+            return Err(invalid(format!(
+                "accounts end at {end}, current_len is {}",
+                self.current_len
+            )));
+        }
+        Ok(accounts)
+    }
+
+    /// Whether `offset`, as passed to [`Self::get_account`], is past the last account.
+    pub const fn is_end(&self, offset: usize) -> bool {
+        match &self.hot {
+            Some(hot) => offset >= hot.num_accounts(),
+            None => offset >= self.current_len,
+        }
+    }
+
+    pub const fn format(&self) -> AccountsFileFormat {
+        match self.hot {
+            Some(_) => AccountsFileFormat::Hot,
+            None => AccountsFileFormat::AppendVec,
+        }
+    }
+
+    /// Returns the raw contents of the AppendVec up to `current_len`.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.map[..self.current_len]
+    }
+
     pub const fn slot(&self) -> u64 {
         self.slot
     }
+
+    pub const fn id(&self) -> u64 {
+        self.id
+    }
 }
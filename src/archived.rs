@@ -1,16 +1,17 @@
 use {
     crate::{
-        deserialize_from, parse_append_vec_name, AccountsDbFields, AppendVec, AppendVecIterator,
-        DeserializableVersionedBank, SerializableAccountStorageEntry, SnapshotError,
-        SnapshotExtractor, SnapshotResult,
+        append_vec::AccountsFileFormat, manifest::SnapshotManifest, parse_append_vec_name,
+        AppendVec, AppendVecIterator, PathContext, ReadProgressTracking, SnapshotError,
+        SnapshotExtractor, SnapshotResult, ACCOUNTS_DIR, SNAPSHOTS_DIR, VERSION_FILE,
     },
     log::info,
+    solana_runtime::snapshot_utils::SNAPSHOT_STATUS_CACHE_FILENAME,
     std::{
+        borrow::Cow,
         fs::File,
         io::{BufReader, Read},
-        path::{Component, Path},
+        path::{Component, Path, PathBuf},
         pin::Pin,
-        time::Instant,
     },
     tar::{Archive, Entries, Entry},
 };
@@ -20,10 +21,14 @@ pub struct ArchiveSnapshotExtractor<Source>
 where
     Source: Read + Unpin + 'static,
 {
-    accounts_db_fields: AccountsDbFields<SerializableAccountStorageEntry>,
+    manifest: SnapshotManifest,
+    status_cache: Option<Vec<u8>>,
+    version: Option<String>,
     _archive: Pin<Box<Archive<zstd::Decoder<'static, BufReader<Source>>>>>,
+    /// AppendVec entry read while searching for the snapshot manifest.
+    first_append_vec: Option<Entry<'static, zstd::Decoder<'static, BufReader<Source>>>>,
     entries: Option<Entries<'static, zstd::Decoder<'static, BufReader<Source>>>>,
-    slot: u64,
+    storage_format: Option<AccountsFileFormat>,
 }
 
 impl<Source> SnapshotExtractor for ArchiveSnapshotExtractor<Source>
@@ -33,8 +38,17 @@ where
     fn iter(&mut self) -> AppendVecIterator<'_> {
         Box::new(self.unboxed_iter())
     }
-    fn slot(&self) -> u64 {
-        self.slot
+    fn manifest(&self) -> &SnapshotManifest {
+        &self.manifest
+    }
+    fn status_cache(&self) -> SnapshotResult<Cow<'_, [u8]>> {
+        self.status_cache
+            .as_deref()
+            .map(Cow::Borrowed)
+            .ok_or(SnapshotError::NoStatusCache)
+    }
+    fn version(&self) -> Option<&str> {
+        self.version.as_deref()
     }
 }
 
@@ -43,107 +57,126 @@ where
     Source: Read + Unpin + 'static,
 {
     pub fn from_reader(source: Source) -> SnapshotResult<Self> {
-        let tar_stream = zstd::stream::read::Decoder::new(source)?;
+        let tar_stream =
+            zstd::stream::read::Decoder::new(source).map_err(SnapshotError::ArchiveError)?;
         let mut archive = Box::pin(Archive::new(tar_stream));
 
         // This is safe as long as we guarantee that entries never gets accessed past drop.
         let archive_static = unsafe { &mut *((&mut *archive) as *mut Archive<_>) };
-        let mut entries = archive_static.entries()?;
+        let mut entries = archive_static
+            .entries()
+            .map_err(SnapshotError::ArchiveError)?;
 
-        // Search for snapshot manifest.
-        let mut snapshot_file: Option<Entry<_>> = None;
+        // Read the manifest, status cache and version file preceding the AppendVecs.
+        let mut manifest = None;
+        let mut status_cache = None;
+        let mut version = None;
+        let mut first_append_vec = None;
         for entry in entries.by_ref() {
-            let entry = entry?;
-            let path = entry.path()?;
+            let mut entry = entry.map_err(SnapshotError::ArchiveError)?;
+            let path = entry
+                .path()
+                .map_err(SnapshotError::ArchiveError)?
+                .into_owned();
+            let entry_error = |source| SnapshotError::ArchiveEntryError {
+                path: path.clone(),
+                source,
+            };
             if Self::is_snapshot_manifest_file(&path) {
-                snapshot_file = Some(entry);
-                break;
+                info!("Opening snapshot manifest: {:?}", &path);
+                let mut buf = Vec::with_capacity(entry.size() as usize);
+                entry.read_to_end(&mut buf).map_err(entry_error)?;
+                manifest = Some(buf);
+            } else if path == Path::new(SNAPSHOTS_DIR).join(SNAPSHOT_STATUS_CACHE_FILENAME) {
+                let mut buf = Vec::with_capacity(entry.size() as usize);
+                entry.read_to_end(&mut buf).map_err(entry_error)?;
+                status_cache = Some(buf);
+            } else if path == Path::new(VERSION_FILE) {
+                let mut buf = String::new();
+                entry.read_to_string(&mut buf).map_err(entry_error)?;
+                version = Some(buf.trim().to_owned());
             } else if Self::is_appendvec_file(&path) {
-                // TODO Support archives where AppendVecs precede snapshot manifests
-                return Err(SnapshotError::UnexpectedAppendVec);
+                first_append_vec = Some((entry, path));
+                break;
             }
         }
-        let snapshot_file = snapshot_file.ok_or(SnapshotError::NoSnapshotManifest)?;
-        //let snapshot_file_len = snapshot_file.size();
-        let snapshot_file_path = snapshot_file.path()?.as_ref().to_path_buf();
-
-        info!("Opening snapshot manifest: {:?}", &snapshot_file_path);
-        let mut snapshot_file = BufReader::new(snapshot_file);
-
-        let pre_unpack = Instant::now();
-        let versioned_bank: DeserializableVersionedBank = deserialize_from(&mut snapshot_file)?;
-        let slot = versioned_bank.slot;
-        drop(versioned_bank);
-        let versioned_bank_post_time = Instant::now();
-
-        let accounts_db_fields: AccountsDbFields<SerializableAccountStorageEntry> =
-            deserialize_from(&mut snapshot_file)?;
-        let accounts_db_fields_post_time = Instant::now();
-        drop(snapshot_file);
-
-        info!(
-            "Read bank fields in {:?}",
-            versioned_bank_post_time - pre_unpack
-        );
-        info!(
-            "Read accounts DB fields in {:?}",
-            accounts_db_fields_post_time - versioned_bank_post_time
-        );
+        // The manifest is parsed once the version file, which may follow it, has been read.
+        let manifest = match (manifest, &first_append_vec) {
+            (Some(manifest), _) => SnapshotManifest::from_bytes(manifest, version.as_deref())?,
+            // TODO Support archives where AppendVecs precede snapshot manifests
+            (None, Some((_, path))) => {
+                return Err(SnapshotError::UnexpectedAppendVec { path: path.clone() })
+            }
+            (None, None) => return Err(SnapshotError::NoSnapshotManifest),
+        };
 
         Ok(ArchiveSnapshotExtractor {
             _archive: archive,
-            accounts_db_fields,
+            manifest,
+            status_cache,
+            version,
+            first_append_vec: first_append_vec.map(|(entry, _)| entry),
             entries: Some(entries),
-            slot,
+            storage_format: None,
         })
     }
 
+    /// Reads the account storage files in `format`, instead of detecting the format of each file.
+    pub const fn with_storage_format(mut self, format: Option<AccountsFileFormat>) -> Self {
+        self.storage_format = format;
+        self
+    }
+
     fn unboxed_iter(&mut self) -> impl Iterator<Item = SnapshotResult<AppendVec>> + '_ {
-        self.entries
+        self.first_append_vec
             .take()
+            .map(Ok)
             .into_iter()
-            .flatten()
+            .chain(self.entries.take().into_iter().flatten())
             .filter_map(|entry| {
                 let mut entry = match entry {
                     Ok(x) => x,
-                    Err(e) => return Some(Err(e.into())),
+                    Err(e) => return Some(Err(SnapshotError::ArchiveError(e))),
                 };
                 let path = match entry.path() {
-                    Ok(x) => x,
-                    Err(e) => return Some(Err(e.into())),
+                    Ok(x) => x.into_owned(),
+                    Err(e) => return Some(Err(SnapshotError::ArchiveError(e))),
                 };
                 let (slot, id) = path.file_name().and_then(parse_append_vec_name)?;
-                Some(self.process_entry(&mut entry, slot, id))
+                Some(self.process_entry(&mut entry, path, slot, id))
             })
     }
 
     fn process_entry(
         &self,
         entry: &mut Entry<'static, zstd::Decoder<'static, BufReader<Source>>>,
+        path: PathBuf,
         slot: u64,
         id: u64,
     ) -> SnapshotResult<AppendVec> {
-        let known_vecs = self
-            .accounts_db_fields
-            .0
-            .get(&slot)
-            .map(|v| &v[..])
-            .unwrap_or(&[]);
-        let known_vec = known_vecs.iter().find(|entry| entry.id == (id as usize));
-        let known_vec = match known_vec {
-            None => return Err(SnapshotError::UnexpectedAppendVec),
-            Some(v) => v,
+        let Some(known_vec) = self.manifest.storage_entry(slot, id) else {
+            return Err(SnapshotError::UnknownAppendVec { slot, id, path });
         };
-        Ok(AppendVec::new_from_reader(
+        let file_size = entry.size();
+        AppendVec::new_from_reader(
             entry,
             known_vec.accounts_current_len,
+            file_size,
+            slot,
+            id,
+            self.storage_format,
+        )
+        .map_err(|source| SnapshotError::InvalidAppendVec {
             slot,
-        )?)
+            id,
+            path,
+            source,
+        })
     }
 
     fn is_snapshot_manifest_file(path: &Path) -> bool {
         let mut components = path.components();
-        if components.next() != Some(Component::Normal("snapshots".as_ref())) {
+        if components.next() != Some(Component::Normal(SNAPSHOTS_DIR.as_ref())) {
             return false;
         }
         let slot_number_str_1 = match components.next() {
@@ -167,7 +200,7 @@ where
 
     fn is_appendvec_file(path: &Path) -> bool {
         let mut components = path.components();
-        if components.next() != Some(Component::Normal("accounts".as_ref())) {
+        if components.next() != Some(Component::Normal(ACCOUNTS_DIR.as_ref())) {
             return false;
         }
         let name = match components.next() {
@@ -178,8 +211,22 @@ where
     }
 }
 
+impl ArchiveSnapshotExtractor<Box<dyn Read>> {
+    /// Reads an archive of `len` bytes from `source`, reporting the read progress to
+    /// `progress_tracking`. `path` identifies the source, it is not opened.
+    pub fn from_reader_with_progress<R: Read + 'static>(
+        source: R,
+        path: &Path,
+        len: u64,
+        progress_tracking: &dyn ReadProgressTracking,
+    ) -> SnapshotResult<Self> {
+        let source = progress_tracking.new_read_progress_tracker(path, Box::new(source), len)?;
+        Self::from_reader(source)
+    }
+}
+
 impl ArchiveSnapshotExtractor<File> {
     pub fn open(path: &Path) -> SnapshotResult<Self> {
-        Self::from_reader(File::open(path)?)
+        Self::from_reader(File::open(path).path_context(path)?)
     }
 }
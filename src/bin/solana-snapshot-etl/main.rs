@@ -1,35 +1,58 @@
 use {
-    clap::{Parser, Subcommand},
-    indicatif::{ProgressBar, ProgressBarIter, ProgressDrawTarget, ProgressStyle},
-    log::info,
-    reqwest::blocking::Response,
+    clap::{CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum},
+    log::{info, warn},
+    serde::Serialize,
+    serde_with::{serde_as, DisplayFromStr},
+    solana_sdk::{clock::Slot, pubkey::Pubkey, signature::Signature},
     solana_snapshot_etl::{
+        accounts::{Account, AccountConsumer, AccountFilter, AccountOptions},
+        anchor::AnchorDecoder,
         append_vec::AppendVec,
         append_vec_iter,
-        archived::ArchiveSnapshotExtractor,
-        parallel::{par_iter_append_vecs, AppendVecConsumer},
-        unpacked::UnpackedSnapshotExtractor,
-        AppendVecIterator, ReadProgressTracking, SnapshotError, SnapshotExtractor, SnapshotResult,
+        check::SnapshotChecker,
+        compact::SnapshotCompactor,
+        decode::AccountDecoders,
+        metrics::metrics,
+        parallel::{
+            par_process_append_vecs, AppendVecProcessor, DiscardOutput, OnError, OutputSink,
+            ParIterConfig, SnapshotInfo,
+        },
+        programs::ProgramsExtractor,
+        rent::RentAuditCollector,
+        repack::SnapshotRepacker,
+        runner::{CommonArgs, Runner},
+        schema::SchemaDecoder,
+        sink::{Pipeline, Sink, DEFAULT_BATCH_SIZE},
+        stakes::StakesCollector,
+        stats::StatsCollector,
+        status_cache::{SignatureStatus, SlotStatuses},
+        sysvars::SysvarCollector,
+        top::{RankBy, TopCollector},
+        unpack::SnapshotUnpacker,
+        SnapshotExtractor,
     },
     std::{
         fs::File,
-        io::{IoSliceMut, Read},
-        path::Path,
-        sync::Arc,
+        io::{self, BufWriter, Write},
+        path::{Path, PathBuf},
+        sync::{Arc, Mutex},
     },
+    tokio::io::{AsyncWrite, AsyncWriteExt},
 };
 
 #[derive(Debug, Parser)]
 #[clap(author, version, about)]
 struct Args {
-    /// Snapshot source (unpacked snapshot, archive file, or HTTP link)
+    #[command(flatten)]
+    common: CommonArgs,
+
+    /// Anchor IDL used to decode accounts of its program, can be repeated
     #[clap(long)]
-    source: String,
+    idl: Vec<PathBuf>,
 
-    /// Number of threads used to process snapshot,
-    /// by default number of CPUs would be used.
+    /// JSON account layout schema used to decode accounts of its program, can be repeated
     #[clap(long)]
-    num_threads: Option<usize>,
+    schema: Vec<PathBuf>,
 
     #[command(subcommand)]
     action: Action,
@@ -39,6 +62,122 @@ struct Args {
 enum Action {
     /// Load accounts and do nothing
     Noop,
+    /// Decode stake and vote accounts and reconcile them with the bank's stakes cache
+    Stakes {
+        /// Output JSON file, stdout if not set
+        #[clap(long)]
+        out: Option<PathBuf>,
+    },
+    /// Extract sysvar accounts (Clock, Rent, EpochSchedule, ...) as a JSON document
+    Sysvars(SysvarsConfig),
+    /// Extract deployed program ELFs with a manifest
+    Programs {
+        /// Output directory for `<program_id>.so` files and `manifest.json`
+        #[clap(long)]
+        out: PathBuf,
+    },
+    /// Report account, owner and AppendVec statistics
+    Stats {
+        /// Output file, stdout if not set
+        #[clap(long)]
+        out: Option<PathBuf>,
+        #[clap(long, value_enum, default_value_t = StatsFormat::Table)]
+        format: StatsFormat,
+        /// Count stale account versions, keeps every pubkey in memory
+        #[clap(long)]
+        count_stale: bool,
+    },
+    /// Rank the largest accounts by lamports, data size or token amount
+    Top {
+        /// Number of accounts to report
+        #[clap(long, default_value_t = 100)]
+        limit: usize,
+        /// Ranking: lamports, data_len or token_amount
+        #[clap(long, default_value = "lamports")]
+        by: RankBy,
+        /// Only rank accounts owned by this program
+        #[clap(long)]
+        owner: Option<Pubkey>,
+        /// Only rank token accounts of this mint
+        #[clap(long)]
+        mint: Option<Pubkey>,
+        /// Output JSON file, stdout if not set
+        #[clap(long)]
+        out: Option<PathBuf>,
+    },
+    /// Classify live accounts as rent-exempt, rent-paying or below the minimum, by owner
+    RentAudit {
+        /// Output JSON file, stdout if not set
+        #[clap(long)]
+        out: Option<PathBuf>,
+        /// Only audit the latest version of each account, keeps every account in memory
+        #[clap(long)]
+        skip_stale: bool,
+    },
+    /// Write a snapshot archive containing only the selected accounts
+    Repack {
+        /// Output `.tar.zst` snapshot archive, or directory to write `snapshot-<slot>-<hash>.tar.zst` to
+        #[clap(long)]
+        out: PathBuf,
+        /// Keep the account with this pubkey, can be repeated
+        #[clap(long)]
+        pubkey: Vec<Pubkey>,
+        /// Keep the accounts owned by this program, can be repeated
+        #[clap(long)]
+        owner: Vec<Pubkey>,
+        /// Do not keep sysvars, native programs, feature, config and staked vote and stake accounts
+        #[clap(long)]
+        skip_runtime_accounts: bool,
+    },
+    /// Unpack a snapshot archive into a snapshot directory
+    Unpack {
+        /// Output snapshot directory
+        #[clap(long)]
+        out: PathBuf,
+    },
+    /// Rewrite the latest version of every account into a few large, pubkey-sorted storage files
+    Compact {
+        /// Output directory, can be used as --source afterwards
+        #[clap(long)]
+        out: PathBuf,
+        /// Maximum size of a storage file in bytes
+        #[clap(long, default_value_t = 1 << 30)]
+        file_size: u64,
+    },
+    /// Check that the AppendVecs match the manifest and that their accounts parse
+    Check {
+        /// Output JSON report file, stdout if not set
+        #[clap(long)]
+        out: Option<PathBuf>,
+    },
+    /// Dump the status cache, or look up the statuses of transactions in it
+    StatusCache {
+        /// Look up the transaction with this signature, can be repeated
+        #[clap(long)]
+        signature: Vec<Signature>,
+        /// Output JSON file, stdout if not set
+        #[clap(long)]
+        out: Option<PathBuf>,
+    },
+    /// Decode accounts of programs with an IDL or schema and write them as JSON lines
+    Decode {
+        /// Output JSON lines file, stdout if not set
+        #[clap(long)]
+        out: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, clap::Args)]
+struct SysvarsConfig {
+    /// Output JSON file, stdout if not set
+    #[clap(long)]
+    out: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum StatsFormat {
+    Table,
+    Json,
 }
 
 #[tokio::main]
@@ -47,152 +186,670 @@ async fn main() -> anyhow::Result<()> {
         env_logger::Env::default().filter_or(env_logger::DEFAULT_FILTER_ENV, "info"),
     );
 
-    let args = Args::parse();
-    let num_threads = args.num_threads.unwrap_or_else(num_cpus::get);
+    let matches = Args::command().get_matches();
+    let args = Args::from_arg_matches(&matches)?;
+    // Subcommands implemented as sinks open the source and write the run reports themselves.
+    if let Action::Sysvars(config) = args.action {
+        return Pipeline::<SysvarSink>::new(args.common, config)
+            .filter(SysvarCollector::account_filter())
+            .run()
+            .await;
+    }
+    let mut runner = Runner::new(
+        args.common.clone(),
+        matches.subcommand_name().expect("subcommand required"),
+    );
+    let result = run(args, &mut runner).await;
+    runner.finish(result)
+}
+
+async fn run(args: Args, runner: &mut Runner) -> anyhow::Result<()> {
+    let mut decoders = AccountDecoders::default();
+    for path in &args.idl {
+        let decoder = AnchorDecoder::from_file(path)
+            .map_err(|err| anyhow::anyhow!("Failed to load IDL {path:?}: {err}"))?;
+        decoders.add(Box::new(decoder));
+    }
+    for path in &args.schema {
+        let decoder = SchemaDecoder::from_file(path)
+            .map_err(|err| anyhow::anyhow!("Failed to load schema {path:?}: {err}"))?;
+        decoders.add(Box::new(decoder));
+    }
+    let decoders = Arc::new(decoders);
 
-    let mut loader = SupportedLoader::new(&args.source, Box::new(LoadProgressTracking {}))?;
-    let bar = Arc::new(create_accounts_progress_bar()?);
+    let (mut loader, info) = runner.open()?;
     match args.action {
         Action::Noop => {
-            par_iter_append_vecs(
-                loader.iter(),
-                || NoopConsumer {
-                    bar: Arc::clone(&bar),
+            runner.run(loader.iter(), &info, || NoopConsumer).await?;
+        }
+        Action::Stakes { out } => {
+            let consumers = runner
+                .run_accounts(
+                    loader.iter(),
+                    &info,
+                    StakesConsumer::default,
+                    &account_options(StakesCollector::account_filter()),
+                )
+                .await?;
+            let mut collector = StakesCollector::default();
+            for consumer in consumers {
+                collector.merge(consumer.collector);
+            }
+            let report = collector.reconcile(&loader.bank().stakes);
+            info!(
+                "Decoded {} stake accounts and {} vote accounts",
+                report.stake_accounts.len(),
+                report.vote_accounts.len()
+            );
+            if !report.undecodable.is_empty() {
+                warn!("Failed to decode {} accounts", report.undecodable.len());
+            }
+            if !report.discrepancies.is_empty() {
+                warn!(
+                    "Found {} discrepancies with the bank stakes cache",
+                    report.discrepancies.len()
+                );
+            }
+            write_json(out.as_deref(), &report)?;
+        }
+        Action::Sysvars(_) => unreachable!("sysvars runs as a sink"),
+        Action::Programs { out } => {
+            let mut extractor = ProgramsExtractor::new(&out)?;
+            let consumers = runner
+                .run_accounts(
+                    loader.iter(),
+                    &info,
+                    || ProgramsConsumer {
+                        extractor: extractor.worker(),
+                    },
+                    &account_options(ProgramsExtractor::account_filter()),
+                )
+                .await?;
+            for consumer in consumers {
+                extractor.merge(consumer.extractor);
+            }
+            let manifest = extractor.finish(loader.slot())?;
+            info!("Extracted {} programs", manifest.programs.len());
+            write_json(Some(&out.join("manifest.json")), &manifest)?;
+        }
+        Action::Stats {
+            out,
+            format,
+            count_stale,
+        } => {
+            let collector = Arc::new(Mutex::new(StatsCollector::new(count_stale)));
+            runner
+                .run(loader.iter(), &info, || StatsConsumer {
+                    collector: Arc::clone(&collector),
+                    count_stale,
+                })
+                .await?;
+            let collector = std::mem::take(&mut *collector.lock().unwrap());
+            let stats = collector.finish(loader.slot());
+            match format {
+                StatsFormat::Table => {
+                    let mut writer = create_output(out.as_deref())?;
+                    write!(writer, "{stats}")?;
+                    writer.flush()?;
+                }
+                StatsFormat::Json => write_json(out.as_deref(), &stats)?,
+            }
+        }
+        Action::Top {
+            limit,
+            by,
+            owner,
+            mint,
+            out,
+        } => {
+            let consumers = runner
+                .run(loader.iter(), &info, || TopConsumer {
+                    collector: TopCollector::new(by, limit, owner, mint),
+                })
+                .await?;
+            let mut collector = TopCollector::new(by, limit, owner, mint);
+            for consumer in consumers {
+                collector.merge(consumer.collector);
+            }
+            write_json(out.as_deref(), &collector.finish())?;
+        }
+        Action::RentAudit { out, skip_stale } => {
+            let rent_collector = loader.bank().rent_collector.clone();
+            let consumers = runner
+                .run(loader.iter(), &info, || RentAuditConsumer {
+                    collector: RentAuditCollector::new(rent_collector.clone(), skip_stale),
+                })
+                .await?;
+            let mut collector = RentAuditCollector::new(rent_collector, skip_stale);
+            for consumer in consumers {
+                collector.merge(consumer.collector);
+            }
+            let audit = collector.finish(loader.slot());
+            info!(
+                "Found {} rent-paying and {} below minimum accounts",
+                audit.total.rent_paying.accounts, audit.total.below_minimum.accounts
+            );
+            write_json(out.as_deref(), &audit)?;
+        }
+        Action::Repack {
+            out,
+            pubkey,
+            owner,
+            skip_runtime_accounts,
+        } => {
+            let mut filter = AccountFilter::new(pubkey, owner);
+            if !skip_runtime_accounts {
+                filter = filter.with_runtime_accounts(loader.bank());
+            }
+            let staging_dir = if out.is_dir() {
+                out.join(".staging")
+            } else {
+                let mut staging_dir = out.clone().into_os_string();
+                staging_dir.push(".staging");
+                staging_dir.into()
+            };
+            let repacker = Arc::new(SnapshotRepacker::new(&staging_dir, filter)?);
+            runner
+                .run(loader.iter(), &info, || RepackConsumer {
+                    repacker: Arc::clone(&repacker),
+                })
+                .await?;
+            let repacker = Arc::into_inner(repacker).expect("consumers dropped");
+            let repacked = repacker.finish(&loader, &out)?;
+            info!(
+                "Wrote {} accounts in {} AppendVecs to {:?}, capitalization {}",
+                repacked.accounts, repacked.append_vecs, repacked.archive, repacked.capitalization
+            );
+            runner
+                .set_filtered_accounts(metrics().accounts.get().saturating_sub(repacked.accounts));
+        }
+        Action::Unpack { out } => {
+            let unpacker = Arc::new(SnapshotUnpacker::new(&out, &loader)?);
+            runner
+                .run(loader.iter(), &info, || UnpackConsumer {
+                    unpacker: Arc::clone(&unpacker),
+                })
+                .await?;
+            let unpacker = Arc::into_inner(unpacker).expect("consumers dropped");
+            unpacker.finish()?;
+            info!("Unpacked snapshot to {:?}", out);
+        }
+        Action::Compact { out, file_size } => {
+            let compactor = Arc::new(SnapshotCompactor::new(&out, &loader)?);
+            runner
+                .run(loader.iter(), &info, || CompactConsumer {
+                    compactor: Arc::clone(&compactor),
+                })
+                .await?;
+            let compactor = Arc::into_inner(compactor).expect("consumers dropped");
+            info!("Writing storage files");
+            let index = compactor.finish(&loader, file_size)?;
+            info!(
+                "Wrote {} accounts to {} storage files, dropped {} stale or closed versions",
+                index.accounts,
+                index.files.len(),
+                index.dropped_versions
+            );
+            runner.set_filtered_accounts(index.dropped_versions);
+        }
+        Action::Check { out } => {
+            let checker = Arc::new(SnapshotChecker::new(loader.manifest()));
+            let iter_checker = Arc::clone(&checker);
+            // The checker verifies and reports bad AppendVecs itself, they are never skipped.
+            par_process_append_vecs(
+                Box::new(
+                    loader
+                        .iter()
+                        .filter_map(move |result| iter_checker.filter_open_errors(result)),
+                ),
+                &info,
+                || CheckConsumer {
+                    checker: Arc::clone(&checker),
+                },
+                DiscardOutput,
+                ParIterConfig {
+                    verify: false,
+                    on_error: OnError::Abort,
+                    ..runner.config()
                 },
-                num_threads,
             )
             .await?;
+            let checker = Arc::into_inner(checker).expect("consumers dropped");
+            let report = checker.finish(loader.slot());
+            write_json(out.as_deref(), &report)?;
+            anyhow::ensure!(
+                report.is_ok(),
+                "Snapshot check failed: {} missing, {} unexpected and {} invalid AppendVecs",
+                report.missing.len(),
+                report.unexpected.len(),
+                report.invalid.len()
+            );
+            info!(
+                "Checked {} AppendVecs with {} accounts",
+                report.valid_append_vecs, report.accounts
+            );
+        }
+        Action::StatusCache { signature, out } => {
+            let status_cache = loader.read_status_cache()?;
+            info!(
+                "Read status cache of {} slots with {} entries",
+                status_cache.slots.len(),
+                status_cache.len()
+            );
+            if signature.is_empty() {
+                let dump = StatusCacheDump {
+                    slot: loader.slot(),
+                    slots: &status_cache.slots,
+                };
+                write_json(out.as_deref(), &dump)?;
+            } else {
+                let lookups: Vec<_> = signature
+                    .into_iter()
+                    .map(|signature| SignatureLookup {
+                        statuses: status_cache.find(&signature),
+                        signature,
+                    })
+                    .collect();
+                let found = lookups
+                    .iter()
+                    .filter(|lookup| !lookup.statuses.is_empty())
+                    .count();
+                info!("Found {} of {} signatures", found, lookups.len());
+                write_json(out.as_deref(), &lookups)?;
+            }
+        }
+        Action::Decode { out } => {
+            anyhow::ensure!(
+                !decoders.is_empty(),
+                "No decoders given, use --idl or --schema"
+            );
+            let sink = WriterSink::create(out.as_deref()).await?;
+            let stats = Arc::new(Mutex::new(DecodeStats::default()));
+            runner
+                .run_with_sink(
+                    loader.iter(),
+                    &info,
+                    || DecodeConsumer {
+                        decoders: Arc::clone(&decoders),
+                        stats: Arc::clone(&stats),
+                    },
+                    sink,
+                )
+                .await?;
+            let stats = stats.lock().unwrap();
+            info!("Decoded {} accounts", stats.decoded);
+            if stats.failed > 0 {
+                warn!("Failed to decode {} accounts", stats.failed);
+            }
+            runner.set_filtered_accounts(
+                metrics()
+                    .accounts
+                    .get()
+                    .saturating_sub(stats.decoded + stats.failed),
+            );
         }
     }
-    bar.finish();
-    info!("Done!");
 
     Ok(())
 }
 
-struct LoadProgressTracking {}
-
-impl ReadProgressTracking for LoadProgressTracking {
-    fn new_read_progress_tracker(
-        &self,
-        _path: &Path,
-        rd: Box<dyn Read>,
-        file_len: u64,
-    ) -> SnapshotResult<Box<dyn Read>> {
-        let progress_bar = ProgressBar::new(file_len).with_style(
-            ProgressStyle::with_template(
-                "{prefix:>10.bold.dim} {spinner:.green} [{bar:.cyan/blue}] {bytes}/{total_bytes} ({percent}%)",
-            )
-            .map_err(|error| SnapshotError::ReadProgressTracking(error.to_string()))?
-            .progress_chars("#>-"),
-        );
-        progress_bar.set_prefix("manifest");
-        Ok(Box::new(LoadProgressTracker {
-            rd: progress_bar.wrap_read(rd),
-            progress_bar,
-        }))
+/// Delivers the accounts matching `filter` in batches of the default size.
+fn account_options(filter: AccountFilter) -> AccountOptions {
+    AccountOptions {
+        filter: Some(Arc::new(filter)),
+        ..AccountOptions::new(DEFAULT_BATCH_SIZE)
     }
 }
 
-struct LoadProgressTracker {
-    progress_bar: ProgressBar,
-    rd: ProgressBarIter<Box<dyn Read>>,
+/// Opens the file at `out` for writing, or stdout.
+fn create_output(out: Option<&Path>) -> io::Result<Box<dyn Write + Send>> {
+    Ok(match out {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(BufWriter::new(io::stdout())),
+    })
+}
+
+/// Writes `value` as JSON to the file at `out`, or to stdout.
+fn write_json<T: Serialize>(out: Option<&Path>, value: &T) -> anyhow::Result<()> {
+    let mut writer = create_output(out)?;
+    serde_json::to_writer(&mut writer, value)?;
+    writeln!(writer)?;
+    writer.flush()?;
+    Ok(())
 }
 
-impl Drop for LoadProgressTracker {
-    fn drop(&mut self) {
-        self.progress_bar.finish()
+struct NoopConsumer;
+
+impl AppendVecProcessor for NoopConsumer {
+    type Output = ();
+
+    fn process(&mut self, append_vec: AppendVec) -> anyhow::Result<()> {
+        let count = append_vec_iter(&append_vec).count();
+        metrics().accounts.inc_by(count as u64);
+        Ok(())
     }
 }
 
-impl Read for LoadProgressTracker {
-    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        self.rd.read(buf)
+#[derive(Default)]
+struct StakesConsumer {
+    collector: StakesCollector,
+}
+
+impl AccountConsumer for StakesConsumer {
+    fn on_accounts(&mut self, accounts: Vec<Account>) -> anyhow::Result<()> {
+        for account in &accounts {
+            self.collector.process_account(account);
+        }
+        Ok(())
     }
+}
+
+/// Sink of the `sysvars` subcommand, which merges the sysvars of the workers.
+struct SysvarSink {
+    out: Option<PathBuf>,
+    slot: Slot,
+}
 
-    fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> std::io::Result<usize> {
-        self.rd.read_vectored(bufs)
+impl Sink for SysvarSink {
+    const NAME: &'static str = "sysvars";
+
+    type Config = SysvarsConfig;
+    type Consumer = SysvarConsumer;
+
+    fn new(config: SysvarsConfig, info: &SnapshotInfo) -> anyhow::Result<Self> {
+        Ok(Self {
+            out: config.out,
+            slot: info.slot,
+        })
     }
 
-    fn read_to_string(&mut self, buf: &mut String) -> std::io::Result<usize> {
-        self.rd.read_to_string(buf)
+    fn create_consumer(&self) -> SysvarConsumer {
+        SysvarConsumer::default()
     }
 
-    fn read_exact(&mut self, buf: &mut [u8]) -> std::io::Result<()> {
-        self.rd.read_exact(buf)
+    fn finish(self, consumers: Vec<SysvarConsumer>) -> anyhow::Result<()> {
+        let mut collector = SysvarCollector::default();
+        for consumer in consumers {
+            collector.merge(consumer.collector);
+        }
+        let sysvars = collector.finish(self.slot);
+        if !sysvars.undecodable.is_empty() {
+            warn!("Failed to decode {} sysvars", sysvars.undecodable.len());
+        }
+        write_json(self.out.as_deref(), &sysvars)
     }
 }
 
-pub enum SupportedLoader {
-    Unpacked(UnpackedSnapshotExtractor),
-    ArchiveFile(ArchiveSnapshotExtractor<File>),
-    ArchiveDownload(ArchiveSnapshotExtractor<Response>),
+#[derive(Default)]
+struct SysvarConsumer {
+    collector: SysvarCollector,
 }
 
-impl SupportedLoader {
-    fn new(source: &str, progress_tracking: Box<dyn ReadProgressTracking>) -> anyhow::Result<Self> {
-        if source.starts_with("http://") || source.starts_with("https://") {
-            Self::new_download(source)
-        } else {
-            Self::new_file(source.as_ref(), progress_tracking).map_err(Into::into)
+impl AccountConsumer for SysvarConsumer {
+    fn on_accounts(&mut self, accounts: Vec<Account>) -> anyhow::Result<()> {
+        for account in &accounts {
+            self.collector.process_account(account);
         }
+        Ok(())
     }
+}
+
+struct ProgramsConsumer {
+    extractor: ProgramsExtractor,
+}
 
-    fn new_download(url: &str) -> anyhow::Result<Self> {
-        let resp = reqwest::blocking::get(url)?;
-        let loader = ArchiveSnapshotExtractor::from_reader(resp)?;
-        info!("Streaming snapshot from HTTP");
-        Ok(Self::ArchiveDownload(loader))
+impl AccountConsumer for ProgramsConsumer {
+    fn on_accounts(&mut self, accounts: Vec<Account>) -> anyhow::Result<()> {
+        for account in &accounts {
+            self.extractor.process_account(account)?;
+        }
+        Ok(())
     }
+}
 
-    fn new_file(
-        path: &Path,
-        progress_tracking: Box<dyn ReadProgressTracking>,
-    ) -> solana_snapshot_etl::SnapshotResult<Self> {
-        Ok(if path.is_dir() {
-            info!("Reading unpacked snapshot");
-            Self::Unpacked(UnpackedSnapshotExtractor::open(path, progress_tracking)?)
-        } else {
-            info!("Reading snapshot archive");
-            Self::ArchiveFile(ArchiveSnapshotExtractor::open(path)?)
-        })
+// The consumers below work on AppendVecs rather than accounts: stats and check need the
+// AppendVecs themselves, repack, unpack and compact copy their stored bytes, decode writes
+// to an output sink, and top and rent-audit read the metadata of every account, which an
+// account consumer would copy along with its data.
+struct StatsConsumer {
+    collector: Arc<Mutex<StatsCollector>>,
+    count_stale: bool,
+}
+
+impl AppendVecProcessor for StatsConsumer {
+    type Output = ();
+
+    fn process(&mut self, append_vec: AppendVec) -> anyhow::Result<()> {
+        let mut collector = StatsCollector::new(self.count_stale);
+        collector.process_append_vec(&append_vec);
+        metrics().accounts.inc_by(collector.accounts());
+        self.collector.lock().unwrap().merge(collector);
+        Ok(())
     }
 }
 
-impl SnapshotExtractor for SupportedLoader {
-    fn iter(&mut self) -> AppendVecIterator<'_> {
-        match self {
-            SupportedLoader::Unpacked(loader) => Box::new(loader.iter()),
-            SupportedLoader::ArchiveFile(loader) => Box::new(loader.iter()),
-            SupportedLoader::ArchiveDownload(loader) => Box::new(loader.iter()),
+struct TopConsumer {
+    collector: TopCollector,
+}
+
+impl AppendVecProcessor for TopConsumer {
+    type Output = ();
+
+    fn process(&mut self, append_vec: AppendVec) -> anyhow::Result<()> {
+        let mut count = 0u64;
+        for handle in append_vec_iter(&append_vec) {
+            if let Some(account) = handle.access() {
+                self.collector.process_account(append_vec.slot(), &account);
+            }
+            count += 1;
         }
+        metrics().accounts.inc_by(count);
+        Ok(())
     }
-    
-    fn slot(&self) -> u64 {
-        match self {
-            SupportedLoader::Unpacked(loader) => loader.slot(),
-            SupportedLoader::ArchiveFile(loader) => loader.slot(),
-            SupportedLoader::ArchiveDownload(loader) => loader.slot(),
+}
+
+struct RentAuditConsumer {
+    collector: RentAuditCollector,
+}
+
+impl AppendVecProcessor for RentAuditConsumer {
+    type Output = ();
+
+    fn process(&mut self, append_vec: AppendVec) -> anyhow::Result<()> {
+        let mut count = 0u64;
+        for handle in append_vec_iter(&append_vec) {
+            if let Some(account) = handle.access() {
+                self.collector.process_account(append_vec.slot(), &account);
+            }
+            count += 1;
         }
+        metrics().accounts.inc_by(count);
+        Ok(())
+    }
+}
+
+struct RepackConsumer {
+    repacker: Arc<SnapshotRepacker>,
+}
+
+impl AppendVecProcessor for RepackConsumer {
+    type Output = ();
+
+    fn process(&mut self, append_vec: AppendVec) -> anyhow::Result<()> {
+        self.repacker.process_append_vec(&append_vec)?;
+        metrics()
+            .accounts
+            .inc_by(append_vec_iter(&append_vec).count() as u64);
+        Ok(())
+    }
+}
+
+struct UnpackConsumer {
+    unpacker: Arc<SnapshotUnpacker>,
+}
+
+impl AppendVecProcessor for UnpackConsumer {
+    type Output = ();
+
+    fn process(&mut self, append_vec: AppendVec) -> anyhow::Result<()> {
+        self.unpacker.write_append_vec(&append_vec)?;
+        metrics()
+            .accounts
+            .inc_by(append_vec_iter(&append_vec).count() as u64);
+        Ok(())
     }
 }
 
-fn create_accounts_progress_bar() -> anyhow::Result<ProgressBar> {
-    let tmpl = ProgressStyle::with_template("{prefix:>10.bold.dim} {spinner:.green} rate={per_sec} processed={human_pos} {elapsed_precise:.cyan}")?;
-    let bar = ProgressBar::with_draw_target(None, ProgressDrawTarget::stderr()).with_style(tmpl);
-    bar.set_prefix("accounts");
-    Ok(bar)
+struct CheckConsumer {
+    checker: Arc<SnapshotChecker>,
 }
 
-struct NoopConsumer {
-    bar: Arc<ProgressBar>,
+impl AppendVecProcessor for CheckConsumer {
+    type Output = ();
+
+    fn process(&mut self, append_vec: AppendVec) -> anyhow::Result<()> {
+        metrics()
+            .accounts
+            .inc_by(self.checker.check_append_vec(&append_vec));
+        Ok(())
+    }
+}
+
+struct CompactConsumer {
+    compactor: Arc<SnapshotCompactor>,
+}
+
+impl AppendVecProcessor for CompactConsumer {
+    type Output = ();
+
+    fn process(&mut self, append_vec: AppendVec) -> anyhow::Result<()> {
+        self.compactor.process_append_vec(&append_vec)?;
+        metrics()
+            .accounts
+            .inc_by(append_vec_iter(&append_vec).count() as u64);
+        Ok(())
+    }
+}
+
+/// Output of the `status-cache` subcommand without signatures.
+#[derive(Serialize)]
+struct StatusCacheDump<'a> {
+    slot: Slot,
+    slots: &'a [SlotStatuses],
+}
+
+/// Statuses of a transaction looked up by the `status-cache` subcommand,
+/// empty if it is not in the status cache.
+#[serde_as]
+#[derive(Serialize)]
+struct SignatureLookup {
+    #[serde_as(as = "DisplayFromStr")]
+    signature: Signature,
+    statuses: Vec<SignatureStatus>,
+}
+
+#[derive(Default)]
+struct DecodeStats {
+    decoded: u64,
+    failed: u64,
+}
+
+/// Line of the `decode` output. Accounts that failed to decode carry an `error` instead of `data`.
+#[serde_as]
+#[derive(Serialize)]
+struct DecodedAccountRecord<'a> {
+    #[serde_as(as = "DisplayFromStr")]
+    pubkey: Pubkey,
+    #[serde_as(as = "DisplayFromStr")]
+    owner: Pubkey,
+    slot: u64,
+    write_version: u64,
+    lamports: u64,
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    type_name: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<&'a serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+struct DecodeConsumer {
+    decoders: Arc<AccountDecoders>,
+    stats: Arc<Mutex<DecodeStats>>,
+}
+
+impl AppendVecProcessor for DecodeConsumer {
+    /// JSON lines of the decoded accounts.
+    type Output = Vec<u8>;
+
+    fn process(&mut self, append_vec: AppendVec) -> anyhow::Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        let mut stats = DecodeStats::default();
+        let mut count = 0u64;
+        for handle in append_vec_iter(&append_vec) {
+            count += 1;
+            let Some(account) = handle.access() else {
+                continue;
+            };
+            let Some(result) = self.decoders.decode(&account) else {
+                continue;
+            };
+            let mut record = DecodedAccountRecord {
+                pubkey: account.meta.pubkey,
+                owner: account.account_meta.owner,
+                slot: append_vec.slot(),
+                write_version: account.meta.write_version_obsolete,
+                lamports: account.account_meta.lamports,
+                type_name: None,
+                data: None,
+                error: None,
+            };
+            match &result {
+                Ok(decoded) => {
+                    record.type_name = Some(&decoded.type_name);
+                    record.data = Some(&decoded.value);
+                    stats.decoded += 1;
+                }
+                Err(err) => {
+                    record.error = Some(err.to_string());
+                    stats.failed += 1;
+                }
+            }
+            serde_json::to_writer(&mut buf, &record)?;
+            buf.push(b'\n');
+        }
+        let mut total = self.stats.lock().unwrap();
+        total.decoded += stats.decoded;
+        total.failed += stats.failed;
+        metrics().accounts.inc_by(count);
+        Ok(buf)
+    }
+}
+
+/// Writes the outputs of processors to a file or stdout.
+struct WriterSink(Box<dyn AsyncWrite + Send + Unpin>);
+
+impl WriterSink {
+    /// Opens the file at `out` for writing, or stdout.
+    async fn create(out: Option<&Path>) -> io::Result<Self> {
+        Ok(Self(match out {
+            Some(path) => Box::new(tokio::io::BufWriter::new(
+                tokio::fs::File::create(path).await?,
+            )),
+            None => Box::new(tokio::io::BufWriter::new(tokio::io::stdout())),
+        }))
+    }
 }
 
 #[async_trait::async_trait]
-impl AppendVecConsumer for NoopConsumer {
-    async fn on_append_vec(&mut self, append_vec: AppendVec) -> anyhow::Result<()> {
-        let count = append_vec_iter(&append_vec).count();
-        self.bar.inc(count as u64);
+impl OutputSink<Vec<u8>> for WriterSink {
+    async fn on_output(&mut self, output: Vec<u8>) -> anyhow::Result<()> {
+        self.0.write_all(&output).await?;
+        Ok(())
+    }
+
+    async fn on_finish(&mut self) -> anyhow::Result<()> {
+        self.0.flush().await?;
         Ok(())
     }
 }
@@ -0,0 +1,149 @@
+//! Integrity checks of a snapshot's AppendVecs against its manifest.
+
+use {
+    crate::{append_vec::AppendVec, manifest::SnapshotManifest, SnapshotError, SnapshotResult},
+    serde::Serialize,
+    solana_sdk::clock::Slot,
+    std::{collections::HashSet, sync::Mutex},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+pub struct AppendVecId {
+    pub slot: Slot,
+    pub id: u64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct AppendVecIssue {
+    pub slot: Slot,
+    pub id: u64,
+    pub error: String,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct CheckReport {
+    pub slot: Slot,
+    /// AppendVecs listed in the manifest.
+    pub append_vecs: u64,
+    /// AppendVecs that were opened and whose accounts parsed.
+    pub valid_append_vecs: u64,
+    pub accounts: u64,
+    /// AppendVecs of the manifest without a file.
+    pub missing: Vec<AppendVecId>,
+    /// AppendVec files not listed in the manifest.
+    pub unexpected: Vec<AppendVecId>,
+    /// AppendVecs too short for their `accounts_current_len`, or with accounts that don't parse.
+    pub invalid: Vec<AppendVecIssue>,
+}
+
+impl CheckReport {
+    pub fn is_ok(&self) -> bool {
+        self.missing.is_empty() && self.unexpected.is_empty() && self.invalid.is_empty()
+    }
+}
+
+/// Checks the AppendVecs of a snapshot against its manifest.
+/// AppendVecs can be checked in parallel.
+pub struct SnapshotChecker {
+    expected: HashSet<AppendVecId>,
+    state: Mutex<CheckState>,
+}
+
+#[derive(Default)]
+struct CheckState {
+    seen: HashSet<AppendVecId>,
+    report: CheckReport,
+}
+
+impl SnapshotChecker {
+    pub fn new(manifest: &SnapshotManifest) -> Self {
+        let expected = manifest
+            .accounts_db_fields
+            .0
+            .iter()
+            .flat_map(|(slot, entries)| {
+                entries.iter().map(|entry| AppendVecId {
+                    slot: *slot,
+                    id: entry.id as u64,
+                })
+            })
+            .collect();
+        Self {
+            expected,
+            state: Mutex::new(CheckState::default()),
+        }
+    }
+
+    /// Records the AppendVecs an extractor failed to open, because they are not in the
+    /// manifest or are invalid. Other errors are passed through.
+    pub fn filter_open_errors(
+        &self,
+        result: SnapshotResult<AppendVec>,
+    ) -> Option<SnapshotResult<AppendVec>> {
+        let mut state = self.state.lock().unwrap();
+        match result {
+            Err(SnapshotError::UnknownAppendVec { slot, id, .. }) => {
+                state.report.unexpected.push(AppendVecId { slot, id });
+                None
+            }
+            Err(SnapshotError::InvalidAppendVec {
+                slot, id, source, ..
+            }) => {
+                state.seen.insert(AppendVecId { slot, id });
+                state.report.invalid.push(AppendVecIssue {
+                    slot,
+                    id,
+                    error: source.to_string(),
+                });
+                None
+            }
+            result => result.map(Some).transpose(),
+        }
+    }
+
+    /// Verifies the accounts of `append_vec`, returns the number of accounts that parsed.
+    pub fn check_append_vec(&self, append_vec: &AppendVec) -> u64 {
+        let id = AppendVecId {
+            slot: append_vec.slot(),
+            id: append_vec.id(),
+        };
+        let result = append_vec.verify_accounts();
+        let mut state = self.state.lock().unwrap();
+        if !state.seen.insert(id) {
+            state.report.invalid.push(AppendVecIssue {
+                slot: id.slot,
+                id: id.id,
+                error: "duplicate AppendVec file".to_owned(),
+            });
+            return 0;
+        }
+        match result {
+            Ok(accounts) => {
+                state.report.valid_append_vecs += 1;
+                state.report.accounts += accounts;
+                accounts
+            }
+            Err(err) => {
+                state.report.invalid.push(AppendVecIssue {
+                    slot: id.slot,
+                    id: id.id,
+                    error: err.to_string(),
+                });
+                0
+            }
+        }
+    }
+
+    pub fn finish(self, slot: Slot) -> CheckReport {
+        let CheckState { seen, mut report } = self.state.into_inner().unwrap();
+        report.slot = slot;
+        report.append_vecs = self.expected.len() as u64;
+        report.missing = self.expected.difference(&seen).copied().collect();
+        report.missing.sort_unstable();
+        report.unexpected.sort_unstable();
+        report
+            .invalid
+            .sort_unstable_by_key(|issue| (issue.slot, issue.id));
+        report
+    }
+}
@@ -0,0 +1,387 @@
+//! Compaction of a snapshot's accounts into a few large, pubkey-sorted storage files.
+//!
+//! Compaction runs in two passes. The first pass copies every stored account version
+//! into one of 256 bucket files, by the first byte of its pubkey. The second pass loads
+//! one bucket at a time, keeps the latest version of each account and writes the accounts
+//! sorted by pubkey into storage files in the AppendVec layout.
+//!
+//! A compacted snapshot directory contains the manifest, with storage entries rewritten
+//! to match the storage files, the original status cache and version file, a
+//! `compact.json` index and the storage files in `storage/`.
+
+use {
+    crate::{
+        append_vec::{AccountsFileFormat, AppendVec},
+        append_vec_iter,
+        manifest::SnapshotManifest,
+        solana::SerializableAccountStorageEntry,
+        unpack::write_snapshot_metadata,
+        AppendVecIterator, PathContext, SnapshotError, SnapshotExtractor, SnapshotResult,
+        SNAPSHOTS_DIR, VERSION_FILE,
+    },
+    serde::{Deserialize, Serialize},
+    solana_accounts_db::{
+        account_storage::meta::{AccountMeta, StoredMeta},
+        accounts_file::ALIGN_BOUNDARY_OFFSET,
+        u64_align,
+    },
+    solana_runtime::snapshot_utils::SNAPSHOT_STATUS_CACHE_FILENAME,
+    solana_sdk::clock::Slot,
+    std::{
+        borrow::Cow,
+        collections::HashMap,
+        fs::{self, File},
+        io::{self, BufReader, BufWriter, Write},
+        mem,
+        path::{Path, PathBuf},
+        sync::Mutex,
+    },
+};
+
+pub const COMPACT_INDEX_FILE: &str = "compact.json";
+const STORAGE_DIR: &str = "storage";
+const BUCKETS_DIR: &str = ".buckets";
+const BUCKETS: usize = 256;
+
+/// Header of a bucket record: slot and length of the stored account that follows.
+const RECORD_HEADER_LEN: usize = 16;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CompactedFile {
+    pub id: u64,
+    /// Bytes used by accounts, like `accounts_current_len` of AppendVecs.
+    pub len: usize,
+    pub accounts: u64,
+}
+
+/// Index of a compacted snapshot directory.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct CompactIndex {
+    pub slot: Slot,
+    pub accounts: u64,
+    /// Stored account versions dropped, because they were stale or closed.
+    pub dropped_versions: u64,
+    pub files: Vec<CompactedFile>,
+}
+
+/// Writes a compacted snapshot directory. AppendVecs can be processed in parallel.
+pub struct SnapshotCompactor {
+    out_dir: PathBuf,
+    buckets: Vec<Mutex<BufWriter<File>>>,
+}
+
+impl SnapshotCompactor {
+    /// Creates the compacted snapshot directory and writes the manifest, status cache
+    /// and version file of `extractor`. The manifest storage entries are rewritten by
+    /// [`Self::finish`].
+    pub fn new<E: SnapshotExtractor>(out_dir: &Path, extractor: &E) -> SnapshotResult<Self> {
+        write_snapshot_metadata(out_dir, extractor)?;
+        let storage_dir = out_dir.join(STORAGE_DIR);
+        fs::create_dir_all(&storage_dir).write_context(&storage_dir)?;
+        let buckets_dir = out_dir.join(BUCKETS_DIR);
+        fs::create_dir_all(&buckets_dir).write_context(&buckets_dir)?;
+        let buckets = (0..BUCKETS)
+            .map(|bucket| {
+                let path = buckets_dir.join(bucket.to_string());
+                let file = File::create(&path).write_context(&path)?;
+                Ok(Mutex::new(BufWriter::new(file)))
+            })
+            .collect::<SnapshotResult<_>>()?;
+        Ok(Self {
+            out_dir: out_dir.to_path_buf(),
+            buckets,
+        })
+    }
+
+    /// Copies the accounts of `append_vec` to their buckets.
+    pub fn process_append_vec(&self, append_vec: &AppendVec) -> io::Result<()> {
+        let mut records = vec![Vec::new(); BUCKETS];
+        for handle in append_vec_iter(append_vec) {
+            let Some(account) = handle.access() else {
+                continue;
+            };
+            let bytes = append_vec.account_bytes(&account);
+            let record = &mut records[account.meta.pubkey.as_ref()[0] as usize];
+            record.extend_from_slice(&append_vec.slot().to_le_bytes());
+            record.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+            record.extend_from_slice(&bytes);
+        }
+        for (bucket, records) in self.buckets.iter().zip(records) {
+            if !records.is_empty() {
+                bucket.lock().unwrap().write_all(&records)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the storage files, with new files started once `max_file_size` would be exceeded,
+    /// and rewrites the manifest of `extractor` with one storage entry per storage file.
+    pub fn finish<E: SnapshotExtractor>(
+        self,
+        extractor: &E,
+        max_file_size: u64,
+    ) -> SnapshotResult<CompactIndex> {
+        let slot = extractor.slot();
+        let buckets_dir = self.out_dir.join(BUCKETS_DIR);
+        for (bucket, writer) in self.buckets.into_iter().enumerate() {
+            writer
+                .into_inner()
+                .unwrap()
+                .flush()
+                .write_context(&buckets_dir.join(bucket.to_string()))?;
+        }
+
+        let mut index = CompactIndex {
+            slot,
+            ..CompactIndex::default()
+        };
+        let storage_dir = self.out_dir.join(STORAGE_DIR);
+        let mut writer = StorageWriter::new(storage_dir.clone(), max_file_size);
+        for bucket in 0..BUCKETS {
+            let path = buckets_dir.join(bucket.to_string());
+            let data = fs::read(&path).path_context(&path)?;
+            fs::remove_file(&path).write_context(&path)?;
+
+            let mut records = Vec::new();
+            let mut offset = 0;
+            while offset < data.len() {
+                let record = Record::parse(&data, offset).path_context(&path)?;
+                offset += RECORD_HEADER_LEN + record.account.len();
+                records.push(record);
+            }
+            // The sort is stable, so that later stores of an account in the same slot stay last.
+            records.sort_by(|a, b| a.key().cmp(&b.key()));
+
+            let mut records = records.into_iter().peekable();
+            while let Some(record) = records.next() {
+                if records
+                    .peek()
+                    .is_some_and(|next| next.pubkey() == record.pubkey())
+                {
+                    index.dropped_versions += 1;
+                    continue;
+                }
+                if record.lamports() == 0 {
+                    index.dropped_versions += 1;
+                    continue;
+                }
+                writer
+                    .append(record.account, &mut index.files)
+                    .write_context(&storage_dir)?;
+                index.accounts += 1;
+            }
+        }
+        writer
+            .finish(&mut index.files)
+            .write_context(&storage_dir)?;
+        fs::remove_dir(&buckets_dir).write_context(&buckets_dir)?;
+
+        let storages = index
+            .files
+            .iter()
+            .map(|file| SerializableAccountStorageEntry {
+                id: file.id as usize,
+                accounts_current_len: file.len,
+            })
+            .collect();
+        let manifest_path = self
+            .out_dir
+            .join(SNAPSHOTS_DIR)
+            .join(slot.to_string())
+            .join(slot.to_string());
+        let mut manifest =
+            BufWriter::new(File::create(&manifest_path).write_context(&manifest_path)?);
+        extractor
+            .manifest()
+            .write_with_storages(&mut manifest, HashMap::from([(slot, storages)]))
+            .and_then(|()| manifest.flush())
+            .write_context(&manifest_path)?;
+
+        let index_path = self.out_dir.join(COMPACT_INDEX_FILE);
+        let mut file = BufWriter::new(File::create(&index_path).write_context(&index_path)?);
+        serde_json::to_writer(&mut file, &index)
+            .map_err(io::Error::from)
+            .and_then(|()| file.flush())
+            .write_context(&index_path)?;
+        Ok(index)
+    }
+}
+
+/// Stored account copied to a bucket.
+struct Record<'a> {
+    slot: Slot,
+    account: &'a [u8],
+}
+
+impl<'a> Record<'a> {
+    fn parse(data: &'a [u8], offset: usize) -> io::Result<Self> {
+        let header = data
+            .get(offset..offset + RECORD_HEADER_LEN)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "truncated bucket"))?;
+        let slot = u64::from_le_bytes(header[..8].try_into().unwrap());
+        let len = u64::from_le_bytes(header[8..].try_into().unwrap()) as usize;
+        let start = offset + RECORD_HEADER_LEN;
+        let account = data
+            .get(start..start + len)
+            .filter(|account| account.len() >= Self::META_LEN)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "truncated bucket"))?;
+        Ok(Self { slot, account })
+    }
+
+    const META_LEN: usize = mem::size_of::<StoredMeta>() + mem::size_of::<AccountMeta>();
+
+    fn pubkey(&self) -> &[u8] {
+        let start = mem::offset_of!(StoredMeta, pubkey);
+        &self.account[start..start + 32]
+    }
+
+    fn write_version(&self) -> u64 {
+        let start = mem::offset_of!(StoredMeta, write_version_obsolete);
+        u64::from_le_bytes(self.account[start..start + 8].try_into().unwrap())
+    }
+
+    fn lamports(&self) -> u64 {
+        let start = mem::size_of::<StoredMeta>() + mem::offset_of!(AccountMeta, lamports);
+        u64::from_le_bytes(self.account[start..start + 8].try_into().unwrap())
+    }
+
+    fn key(&self) -> (&[u8], Slot, u64) {
+        (self.pubkey(), self.slot, self.write_version())
+    }
+}
+
+/// Appends accounts to storage files, starting a new file when the current one is full.
+struct StorageWriter {
+    dir: PathBuf,
+    max_file_size: u64,
+    current: Option<(BufWriter<File>, CompactedFile)>,
+}
+
+impl StorageWriter {
+    const fn new(dir: PathBuf, max_file_size: u64) -> Self {
+        Self {
+            dir,
+            max_file_size,
+            current: None,
+        }
+    }
+
+    fn append(&mut self, account: &[u8], files: &mut Vec<CompactedFile>) -> io::Result<()> {
+        if let Some((_, file)) = &self.current {
+            if file.len > 0 && (u64_align!(file.len) + account.len()) as u64 > self.max_file_size {
+                self.finish(files)?;
+            }
+        }
+        if self.current.is_none() {
+            let id = files.len() as u64;
+            let writer = BufWriter::new(File::create(self.dir.join(id.to_string()))?);
+            let file = CompactedFile {
+                id,
+                len: 0,
+                accounts: 0,
+            };
+            self.current = Some((writer, file));
+        }
+        let (writer, file) = self.current.as_mut().unwrap();
+        let aligned_len = u64_align!(file.len);
+        writer.write_all(&[0u8; 8][..aligned_len - file.len])?;
+        writer.write_all(account)?;
+        file.len = aligned_len + account.len();
+        file.accounts += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self, files: &mut Vec<CompactedFile>) -> io::Result<()> {
+        if let Some((mut writer, file)) = self.current.take() {
+            writer.flush()?;
+            files.push(file);
+        }
+        Ok(())
+    }
+}
+
+/// Extracts accounts from a compacted snapshot directory.
+///
+/// Storage files hold the latest version of accounts from many slots, they are
+/// all reported with the snapshot slot, as in the storage entries of the manifest.
+pub struct CompactedSnapshotExtractor {
+    root: PathBuf,
+    index: CompactIndex,
+    manifest: SnapshotManifest,
+    version: Option<String>,
+}
+
+impl SnapshotExtractor for CompactedSnapshotExtractor {
+    fn iter(&mut self) -> AppendVecIterator<'_> {
+        Box::new(self.index.files.iter().map(|file| {
+            let path = self.root.join(STORAGE_DIR).join(file.id.to_string());
+            AppendVec::new_from_file(
+                &path,
+                file.len,
+                self.index.slot,
+                file.id,
+                Some(AccountsFileFormat::AppendVec),
+            )
+            .map_err(|source| SnapshotError::InvalidAppendVec {
+                slot: self.index.slot,
+                id: file.id,
+                path,
+                source,
+            })
+        }))
+    }
+    fn manifest(&self) -> &SnapshotManifest {
+        &self.manifest
+    }
+    fn status_cache(&self) -> SnapshotResult<Cow<'_, [u8]>> {
+        let path = self
+            .root
+            .join(SNAPSHOTS_DIR)
+            .join(SNAPSHOT_STATUS_CACHE_FILENAME);
+        Ok(Cow::Owned(fs::read(&path).path_context(&path)?))
+    }
+    fn version(&self) -> Option<&str> {
+        self.version.as_deref()
+    }
+}
+
+impl CompactedSnapshotExtractor {
+    pub fn is_compacted(path: &Path) -> bool {
+        path.join(COMPACT_INDEX_FILE).is_file()
+    }
+
+    pub fn open(path: &Path) -> SnapshotResult<Self> {
+        let index_path = path.join(COMPACT_INDEX_FILE);
+        let index: CompactIndex = serde_json::from_reader(BufReader::new(
+            File::open(&index_path).path_context(&index_path)?,
+        ))
+        .map_err(io::Error::from)
+        .path_context(&index_path)?;
+
+        let slot = index.slot.to_string();
+        let manifest_path = path.join(SNAPSHOTS_DIR).join(&slot).join(&slot);
+        if !manifest_path.is_file() {
+            return Err(SnapshotError::NoSnapshotManifest);
+        }
+        let version_path = path.join(VERSION_FILE);
+        let version = if version_path.is_file() {
+            let version = fs::read_to_string(&version_path).path_context(&version_path)?;
+            Some(version.trim().to_owned())
+        } else {
+            None
+        };
+        let manifest = fs::read(&manifest_path).path_context(&manifest_path)?;
+        let manifest = SnapshotManifest::from_bytes(manifest, version.as_deref())?;
+
+        Ok(Self {
+            root: path.to_path_buf(),
+            index,
+            manifest,
+            version,
+        })
+    }
+
+    pub const fn index(&self) -> &CompactIndex {
+        &self.index
+    }
+}
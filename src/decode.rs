@@ -0,0 +1,221 @@
+//! Decoding of program-owned account data into JSON.
+
+use {
+    crate::append_vec::StoredAccountMeta,
+    serde_json::{Number, Value},
+    solana_sdk::pubkey::Pubkey,
+    std::collections::HashMap,
+    thiserror::Error,
+};
+
+#[derive(Error, Debug)]
+pub enum DecodeError {
+    #[error("Unexpected end of data at offset {offset}, need {needed} bytes")]
+    UnexpectedEof { offset: usize, needed: usize },
+    #[error("Invalid {kind} at offset {offset}")]
+    InvalidValue { kind: &'static str, offset: usize },
+    #[error("Unsupported layout: {0}")]
+    Unsupported(String),
+}
+
+pub type DecodeResult<T> = Result<T, DecodeError>;
+
+/// Account data decoded by an [`AccountDecoder`].
+#[derive(Clone, Debug)]
+pub struct DecodedAccount {
+    /// Name of the account type the data was decoded as.
+    pub type_name: String,
+    pub value: Value,
+}
+
+/// Decodes the accounts of a single program.
+pub trait AccountDecoder: Send + Sync {
+    /// Program owning the accounts this decoder understands.
+    fn program_id(&self) -> Pubkey;
+
+    /// Decodes account data.
+    /// Returns `None` if the data does not match any account type known to the decoder.
+    fn decode(&self, data: &[u8]) -> Option<DecodeResult<DecodedAccount>>;
+}
+
+/// Dispatches accounts to the decoders registered for their owner.
+#[derive(Default)]
+pub struct AccountDecoders {
+    decoders: HashMap<Pubkey, Vec<Box<dyn AccountDecoder>>>,
+}
+
+impl AccountDecoders {
+    pub fn add(&mut self, decoder: Box<dyn AccountDecoder>) {
+        self.decoders
+            .entry(decoder.program_id())
+            .or_default()
+            .push(decoder);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.decoders.is_empty()
+    }
+
+    pub fn has_decoder(&self, owner: &Pubkey) -> bool {
+        self.decoders.contains_key(owner)
+    }
+
+    /// Decodes an account with the first decoder of its owner that recognizes it.
+    pub fn decode(&self, account: &StoredAccountMeta) -> Option<DecodeResult<DecodedAccount>> {
+        self.decoders
+            .get(&account.account_meta.owner)?
+            .iter()
+            .find_map(|decoder| decoder.decode(account.data))
+    }
+}
+
+/// Fixed-size types shared by the account layouts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Primitive {
+    Bool,
+    U8,
+    U16,
+    U32,
+    U64,
+    U128,
+    I8,
+    I16,
+    I32,
+    I64,
+    I128,
+    F32,
+    F64,
+    Pubkey,
+}
+
+impl Primitive {
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "bool" => Self::Bool,
+            "u8" => Self::U8,
+            "u16" => Self::U16,
+            "u32" => Self::U32,
+            "u64" => Self::U64,
+            "u128" => Self::U128,
+            "i8" => Self::I8,
+            "i16" => Self::I16,
+            "i32" => Self::I32,
+            "i64" => Self::I64,
+            "i128" => Self::I128,
+            "f32" => Self::F32,
+            "f64" => Self::F64,
+            "pubkey" | "publicKey" => Self::Pubkey,
+            _ => return None,
+        })
+    }
+
+    pub const fn size(self) -> usize {
+        match self {
+            Self::Bool | Self::U8 | Self::I8 => 1,
+            Self::U16 | Self::I16 => 2,
+            Self::U32 | Self::I32 | Self::F32 => 4,
+            Self::U64 | Self::I64 | Self::F64 => 8,
+            Self::U128 | Self::I128 => 16,
+            Self::Pubkey => 32,
+        }
+    }
+}
+
+/// Little-endian reader over account data.
+pub struct DataReader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> DataReader<'a> {
+    pub const fn new(data: &'a [u8], offset: usize) -> Self {
+        Self { data, offset }
+    }
+
+    pub const fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub const fn remaining(&self) -> usize {
+        self.data.len().saturating_sub(self.offset)
+    }
+
+    pub fn read_bytes(&mut self, len: usize) -> DecodeResult<&'a [u8]> {
+        let bytes = self
+            .offset
+            .checked_add(len)
+            .and_then(|end| self.data.get(self.offset..end))
+            .ok_or(DecodeError::UnexpectedEof {
+                offset: self.offset,
+                needed: len,
+            })?;
+        self.offset += len;
+        Ok(bytes)
+    }
+
+    fn read_array<const N: usize>(&mut self) -> DecodeResult<[u8; N]> {
+        Ok(self.read_bytes(N)?.try_into().expect("length checked"))
+    }
+
+    pub fn read_u8(&mut self) -> DecodeResult<u8> {
+        Ok(self.read_array::<1>()?[0])
+    }
+
+    pub fn read_u32(&mut self) -> DecodeResult<u32> {
+        Ok(u32::from_le_bytes(self.read_array()?))
+    }
+
+    /// Reads a Borsh-style boolean tag, e.g. of an `Option`.
+    pub fn read_bool(&mut self) -> DecodeResult<bool> {
+        let offset = self.offset;
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(DecodeError::InvalidValue {
+                kind: "bool",
+                offset,
+            }),
+        }
+    }
+
+    /// Reads the 4-byte tag of a C-style option, which always stores its value.
+    pub fn read_coption_tag(&mut self) -> DecodeResult<bool> {
+        let offset = self.offset;
+        match self.read_u32()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(DecodeError::InvalidValue {
+                kind: "coption",
+                offset,
+            }),
+        }
+    }
+
+    pub fn read_primitive(&mut self, primitive: Primitive) -> DecodeResult<Value> {
+        Ok(match primitive {
+            Primitive::Bool => Value::Bool(self.read_bool()?),
+            Primitive::U8 => self.read_u8()?.into(),
+            Primitive::U16 => u16::from_le_bytes(self.read_array()?).into(),
+            Primitive::U32 => self.read_u32()?.into(),
+            Primitive::U64 => u64::from_le_bytes(self.read_array()?).into(),
+            // 128-bit integers exceed the precision of most JSON consumers.
+            Primitive::U128 => u128::from_le_bytes(self.read_array()?).to_string().into(),
+            Primitive::I8 => i8::from_le_bytes(self.read_array()?).into(),
+            Primitive::I16 => i16::from_le_bytes(self.read_array()?).into(),
+            Primitive::I32 => i32::from_le_bytes(self.read_array()?).into(),
+            Primitive::I64 => i64::from_le_bytes(self.read_array()?).into(),
+            Primitive::I128 => i128::from_le_bytes(self.read_array()?).to_string().into(),
+            Primitive::F32 => float_value(f32::from_le_bytes(self.read_array()?).into()),
+            Primitive::F64 => float_value(f64::from_le_bytes(self.read_array()?)),
+            Primitive::Pubkey => Pubkey::new_from_array(self.read_array()?)
+                .to_string()
+                .into(),
+        })
+    }
+}
+
+fn float_value(value: f64) -> Value {
+    Number::from_f64(value)
+        .map(Value::Number)
+        .unwrap_or(Value::Null)
+}
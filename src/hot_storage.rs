@@ -0,0 +1,256 @@
+//! Reading of hot storage files, the tiered storage account file format of newer validators.
+//!
+//! A hot storage file consists of the account entries, an index block with the
+//! addresses and offsets of the accounts, an owners block and a footer ending in a
+//! magic number. Accounts are addressed by their position in the index block.
+
+use {
+    crate::append_vec::{AccountsFileFormat, StoredAccountMeta},
+    memmap2::Mmap,
+    solana_accounts_db::{
+        account_storage::meta::{AccountMeta, StoredMeta},
+        tiered_storage::{
+            file::TieredStorageMagicNumber,
+            footer::{AccountBlockFormat, AccountMetaFormat, TieredStorageFooter, FOOTER_SIZE},
+            hot::HotAccountMeta,
+            index::IndexOffset,
+            meta::TieredAccountMeta,
+            mmap_utils::get_pod,
+        },
+    },
+    solana_sdk::{hash::Hash, pubkey::Pubkey},
+    std::{borrow::Cow, io, mem},
+};
+
+/// Offsets in the index block are in units of the alignment of hot accounts.
+const HOT_ACCOUNT_ALIGNMENT: usize = 8;
+
+pub(crate) struct HotStorage {
+    footer: TieredStorageFooter,
+}
+
+impl HotStorage {
+    /// Reads and validates the footer of a hot storage file. Returns `None` for files
+    /// in the AppendVec layout, which don't end with the tiered storage magic number,
+    /// unless `format` overrides the detection.
+    pub(crate) fn open(map: &Mmap, format: Option<AccountsFileFormat>) -> io::Result<Option<Self>> {
+        match format {
+            Some(AccountsFileFormat::AppendVec) => return Ok(None),
+            Some(AccountsFileFormat::Hot) => {}
+            None if !has_magic_number(map) => return Ok(None),
+            None => {}
+        }
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+        // The footer is read in place, it must be aligned.
+        if map.len() < FOOTER_SIZE || map.len() % HOT_ACCOUNT_ALIGNMENT != 0 {
+            return Err(invalid(format!(
+                "{} bytes is not the size of a hot storage file",
+                map.len()
+            )));
+        }
+        let footer = *TieredStorageFooter::new_from_mmap(map)
+            .map_err(|err| invalid(format!("invalid tiered storage footer: {err}")))?;
+        if footer.account_meta_format != AccountMetaFormat::Hot
+            || footer.account_block_format != AccountBlockFormat::AlignedRaw
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!(
+                    "unsupported tiered storage format: {:?} account meta, {:?} account blocks",
+                    footer.account_meta_format, footer.account_block_format
+                ),
+            ));
+        }
+        Self::validate_footer(&footer, map.len()).map_err(invalid)?;
+        Ok(Some(Self { footer }))
+    }
+
+    /// Checks that the index block, owners block and footer follow each other
+    /// within a file of `len` bytes.
+    fn validate_footer(footer: &TieredStorageFooter, len: usize) -> Result<(), String> {
+        let footer_offset = (len as u64)
+            .checked_sub(footer.footer_size)
+            .ok_or("footer is larger than the file")?;
+        if footer.index_block_offset % mem::size_of::<u32>() as u64 != 0 {
+            return Err("unaligned index block".to_owned());
+        }
+        let index_entry_size = (mem::size_of::<Pubkey>() + mem::size_of::<u32>()) as u64;
+        let index_block_end = u64::from(footer.account_entry_count)
+            .checked_mul(index_entry_size)
+            .and_then(|len| footer.index_block_offset.checked_add(len));
+        if index_block_end.map_or(true, |end| end > footer.owners_block_offset) {
+            return Err("index block overlaps the owners block".to_owned());
+        }
+        let owners_block_end = u64::from(footer.owner_count)
+            .checked_mul(mem::size_of::<Pubkey>() as u64)
+            .and_then(|len| footer.owners_block_offset.checked_add(len));
+        if owners_block_end.map_or(true, |end| end > footer_offset) {
+            return Err("owners block overlaps the footer".to_owned());
+        }
+        Ok(())
+    }
+
+    pub(crate) const fn num_accounts(&self) -> usize {
+        self.footer.account_entry_count as usize
+    }
+
+    /// Returns the account at `index` and the index of the next account.
+    ///
+    /// Hot storage doesn't store write versions and account hashes, they are
+    /// reported as zero and the default hash.
+    pub(crate) fn get_account<'a>(
+        &self,
+        map: &'a Mmap,
+        index: usize,
+    ) -> Option<(StoredAccountMeta<'a>, usize)> {
+        if index >= self.num_accounts() {
+            return None;
+        }
+        let footer = &self.footer;
+        let start = self.account_offset(map, index)?;
+        // Hot accounts don't store their size, an account ends where the next one starts.
+        let end = if index + 1 == self.num_accounts() {
+            footer.index_block_offset as usize
+        } else {
+            self.account_offset(map, index + 1)?
+        };
+        let (meta, block_start) = get_pod::<HotAccountMeta>(map, start).ok()?;
+        let block = map.get(block_start..end)?;
+        let address = footer
+            .index_block_format
+            .get_account_address(map, footer, IndexOffset(index as u32))
+            .ok()?;
+        let owner = footer
+            .owners_block_format
+            .get_owner_address(map, footer, meta.owner_offset())
+            .ok()?;
+        let data = meta.account_data(block);
+        Some((
+            StoredAccountMeta {
+                meta: Cow::Owned(StoredMeta {
+                    write_version_obsolete: 0,
+                    data_len: data.len() as u64,
+                    pubkey: *address,
+                }),
+                account_meta: Cow::Owned(AccountMeta {
+                    lamports: meta.lamports(),
+                    rent_epoch: meta.final_rent_epoch(block),
+                    owner: *owner,
+                    executable: meta.flags().executable(),
+                }),
+                data,
+                offset: index,
+                stored_size: end - start,
+                hash: Cow::Owned(Hash::default()),
+            },
+            index + 1,
+        ))
+    }
+
+    /// Returns the byte offset of the account at `index`.
+    fn account_offset(&self, map: &Mmap, index: usize) -> Option<usize> {
+        let offset = self.footer.index_block_offset as usize
+            + mem::size_of::<Pubkey>() * self.num_accounts()
+            + mem::size_of::<u32>() * index;
+        let (offset, _) = get_pod::<u32>(map, offset).ok()?;
+        Some(*offset as usize * HOT_ACCOUNT_ALIGNMENT)
+    }
+}
+
+/// Whether `map` ends with the magic number of tiered storage files.
+fn has_magic_number(map: &[u8]) -> bool {
+    map.ends_with(&TieredStorageMagicNumber::default().0.to_le_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        memmap2::MmapMut,
+        solana_accounts_db::tiered_storage::{hot::HOT_FORMAT, TieredStorage},
+        solana_sdk::account::{AccountSharedData, ReadableAccount, WritableAccount},
+        std::fs,
+    };
+
+    /// Contents of a hot storage file holding `accounts`.
+    fn hot_storage_file(accounts: &[(Pubkey, AccountSharedData)]) -> Vec<u8> {
+        // Tests run in parallel, name the file after the first account.
+        let path = std::env::temp_dir().join(format!("hot-storage-test-{}", accounts[0].0));
+        let accounts: Vec<_> = accounts
+            .iter()
+            .map(|(pubkey, account)| (pubkey, account))
+            .collect();
+        // The file is removed when the storage is dropped.
+        let storage = TieredStorage::new_writable(&path);
+        storage
+            .write_accounts(&(0, &accounts[..]), 0, &HOT_FORMAT)
+            .unwrap();
+        fs::read(&path).unwrap()
+    }
+
+    fn map(bytes: &[u8]) -> Mmap {
+        let mut map = MmapMut::map_anon(bytes.len()).unwrap();
+        map.copy_from_slice(bytes);
+        map.make_read_only().unwrap()
+    }
+
+    fn accounts() -> Vec<(Pubkey, AccountSharedData)> {
+        let owners = [Pubkey::new_unique(), Pubkey::new_unique()];
+        (0..5)
+            .map(|i| {
+                let mut account = AccountSharedData::new(1_000 + i as u64, i * 13, &owners[i % 2]);
+                account.data_as_mut_slice().fill(i as u8);
+                account.set_executable(i == 3);
+                account.set_rent_epoch(if i == 4 { 42 } else { u64::MAX });
+                (Pubkey::new_unique(), account)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reads_back_written_accounts() {
+        let accounts = accounts();
+        let map = map(&hot_storage_file(&accounts));
+        let hot = HotStorage::open(&map, None).unwrap().unwrap();
+        assert_eq!(hot.num_accounts(), accounts.len());
+        let mut index = 0;
+        for (pubkey, account) in &accounts {
+            let (stored, next) = hot.get_account(&map, index).unwrap();
+            assert_eq!(stored.meta.pubkey, *pubkey);
+            assert_eq!(stored.meta.data_len, account.data().len() as u64);
+            assert_eq!(stored.account_meta.lamports, account.lamports());
+            assert_eq!(stored.account_meta.owner, *account.owner());
+            assert_eq!(stored.account_meta.executable, account.executable());
+            assert_eq!(stored.account_meta.rent_epoch, account.rent_epoch());
+            assert_eq!(stored.data, account.data());
+            index = next;
+        }
+        assert!(hot.get_account(&map, index).is_none());
+    }
+
+    #[test]
+    fn files_without_magic_number_are_append_vecs() {
+        let mut bytes = hot_storage_file(&accounts());
+        *bytes.last_mut().unwrap() ^= 0xff;
+        let map = map(&bytes);
+        assert!(HotStorage::open(&map, None).unwrap().is_none());
+        assert!(HotStorage::open(&map, Some(AccountsFileFormat::Hot)).is_err());
+    }
+
+    #[test]
+    fn append_vec_override_skips_detection() {
+        let map = map(&hot_storage_file(&accounts()));
+        assert!(HotStorage::open(&map, Some(AccountsFileFormat::AppendVec))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn rejects_footer_with_blocks_past_the_footer() {
+        let mut bytes = hot_storage_file(&accounts());
+        let offset = bytes.len() - FOOTER_SIZE + mem::offset_of!(TieredStorageFooter, owner_count);
+        bytes[offset..offset + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        let err = HotStorage::open(&map(&bytes), None).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -1,46 +1,195 @@
 use {
     crate::{
         append_vec::{AppendVec, StoredAccountMeta},
-        solana::{
-            deserialize_from, AccountsDbFields, DeserializableVersionedBank,
-            SerializableAccountStorageEntry,
-        },
+        manifest::SnapshotManifest,
+        solana::DeserializableVersionedBank,
+        status_cache::StatusCache,
+    },
+    log::warn,
+    solana_sdk::clock::Slot,
+    std::{
+        borrow::Cow,
+        ffi::OsStr,
+        fmt, io,
+        io::Read,
+        path::{Path, PathBuf},
+        str::FromStr,
     },
-    std::{ffi::OsStr, io::Read, path::Path, str::FromStr},
     thiserror::Error,
 };
 
+pub mod accounts;
+pub mod anchor;
 pub mod append_vec;
 pub mod archived;
+pub mod check;
+pub mod compact;
+pub mod decode;
+mod hot_storage;
+pub mod loader;
+pub mod manifest;
+pub mod metrics;
 pub mod parallel;
+pub mod programs;
+pub mod progress;
+pub mod rent;
+pub mod repack;
+pub mod runner;
+pub mod schema;
+pub mod sink;
 pub mod solana;
+pub mod stakes;
+pub mod stats;
+pub mod status_cache;
+pub mod sysvars;
+pub mod top;
+pub mod unpack;
 pub mod unpacked;
+pub mod versions;
 
 const SNAPSHOTS_DIR: &str = "snapshots";
+const ACCOUNTS_DIR: &str = "accounts";
+const VERSION_FILE: &str = "version";
 
 #[derive(Error, Debug)]
 pub enum SnapshotError {
     #[error("{0}")]
-    IOError(#[from] std::io::Error),
-    #[error("Failed to deserialize: {0}")]
-    BincodeError(#[from] bincode::Error),
+    IOError(io::Error),
+    #[error("Failed to read {path:?}: {source}")]
+    FileError { path: PathBuf, source: io::Error },
+    #[error("Failed to write {path:?}: {source}")]
+    WriteError { path: PathBuf, source: io::Error },
+    #[error("Failed to read snapshot archive: {0}")]
+    ArchiveError(io::Error),
+    #[error("Failed to read archive entry {path:?}: {source}")]
+    ArchiveEntryError { path: PathBuf, source: io::Error },
+    #[error("Failed to deserialize {section} of the snapshot manifest at byte {offset}: {source}")]
+    ManifestError {
+        section: ManifestSection,
+        /// Byte offset of the section in the manifest.
+        offset: usize,
+        source: bincode::Error,
+    },
+    #[error("Failed to serialize {section} of the snapshot manifest: {source}")]
+    ManifestWriteError {
+        section: ManifestSection,
+        source: bincode::Error,
+    },
+    #[error("Bank hash of slot {0} does not match its accounts delta hash")]
+    UnverifiedBankHash(Slot),
+    #[error("Failed to deserialize status cache: {0}")]
+    StatusCacheError(bincode::Error),
     #[error("Missing status cache")]
     NoStatusCache,
     #[error("No snapshot manifest file found")]
     NoSnapshotManifest,
-    #[error("Unexpected AppendVec")]
-    UnexpectedAppendVec,
+    #[error("AppendVec {path:?} precedes the snapshot manifest")]
+    UnexpectedAppendVec { path: PathBuf },
+    #[error("Missing AppendVec {slot}.{id}")]
+    MissingAppendVec { slot: Slot, id: u64 },
+    #[error("AppendVec {slot}.{id} at {path:?} is not in the snapshot manifest")]
+    UnknownAppendVec { slot: Slot, id: u64, path: PathBuf },
+    #[error("Invalid AppendVec {slot}.{id} at {path:?}: {source}")]
+    InvalidAppendVec {
+        slot: Slot,
+        id: u64,
+        /// AppendVec file, or archive entry.
+        path: PathBuf,
+        source: io::Error,
+    },
+    #[error("Unsupported snapshot version: {0}")]
+    UnsupportedSnapshotVersion(String),
     #[error("Failed to create read progress tracking: {0}")]
     ReadProgressTracking(String),
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SnapshotErrorKind {
+    /// The error is confined to one AppendVec, the other AppendVecs can still be read.
+    Recoverable,
+    /// The snapshot can't be read any further.
+    Fatal,
+}
+
+impl SnapshotError {
+    pub const fn kind(&self) -> SnapshotErrorKind {
+        match self {
+            Self::UnknownAppendVec { .. } | Self::InvalidAppendVec { .. } => {
+                SnapshotErrorKind::Recoverable
+            }
+            _ => SnapshotErrorKind::Fatal,
+        }
+    }
+
+    pub const fn is_recoverable(&self) -> bool {
+        matches!(self.kind(), SnapshotErrorKind::Recoverable)
+    }
+}
+
+/// Section of the snapshot manifest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ManifestSection {
+    BankFields,
+    AccountsDbFields,
+    ExtraFields,
+}
+
+impl fmt::Display for ManifestSection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::BankFields => "bank fields",
+            Self::AccountsDbFields => "accounts DB fields",
+            Self::ExtraFields => "extra fields",
+        })
+    }
+}
+
+/// Attaches the path of the file being read or written to I/O errors.
+trait PathContext<T> {
+    fn path_context(self, path: &Path) -> SnapshotResult<T>;
+    fn write_context(self, path: &Path) -> SnapshotResult<T>;
+}
+
+impl<T> PathContext<T> for io::Result<T> {
+    fn path_context(self, path: &Path) -> SnapshotResult<T> {
+        self.map_err(|source| SnapshotError::FileError {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+
+    fn write_context(self, path: &Path) -> SnapshotResult<T> {
+        self.map_err(|source| SnapshotError::WriteError {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+}
+
 pub type SnapshotResult<T> = Result<T, SnapshotError>;
 
 pub type AppendVecIterator<'a> = Box<dyn Iterator<Item = SnapshotResult<AppendVec>> + 'a>;
 
 pub trait SnapshotExtractor: Sized {
     fn iter(&mut self) -> AppendVecIterator<'_>;
-    fn slot(&self) -> u64;
+    fn manifest(&self) -> &SnapshotManifest;
+    /// Serialized status cache of the snapshot.
+    fn status_cache(&self) -> SnapshotResult<Cow<'_, [u8]>>;
+    /// Contents of the snapshot version file, if the snapshot has one.
+    fn version(&self) -> Option<&str>;
+
+    fn slot(&self) -> u64 {
+        self.manifest().bank.slot
+    }
+
+    fn bank(&self) -> &DeserializableVersionedBank {
+        &self.manifest().bank
+    }
+
+    /// Deserializes the status cache of the snapshot.
+    fn read_status_cache(&self) -> SnapshotResult<StatusCache> {
+        StatusCache::from_bytes(&self.status_cache()?)
+    }
 }
 
 fn parse_append_vec_name(name: &OsStr) -> Option<(u64, u64)> {
@@ -54,17 +203,43 @@ fn parse_append_vec_name(name: &OsStr) -> Option<(u64, u64)> {
     }
 }
 
+/// Verifies the accounts of `append_vec`, see [`AppendVec::verify_accounts`].
+/// Returns the number of accounts, or an [`SnapshotError::InvalidAppendVec`] naming
+/// the AppendVec by its path in the snapshot.
+pub fn verify_append_vec(append_vec: &AppendVec) -> SnapshotResult<u64> {
+    let (slot, id) = (append_vec.slot(), append_vec.id());
+    append_vec
+        .verify_accounts()
+        .map_err(|source| SnapshotError::InvalidAppendVec {
+            slot,
+            id,
+            path: Path::new(ACCOUNTS_DIR).join(format!("{slot}.{id}")),
+            source,
+        })
+}
+
+/// Iterates the accounts of `append_vec`. Logs a warning if an account before the end
+/// of the AppendVec doesn't parse. The parallel iterators of [`parallel`] verify AppendVecs
+/// with [`verify_append_vec`] before consumers iterate them.
 pub fn append_vec_iter(append_vec: &AppendVec) -> impl Iterator<Item = StoredAccountMetaHandle> {
     let mut offset = 0usize;
     std::iter::repeat_with(move || {
-        append_vec.get_account(offset).map(|(_, next_offset)| {
+        let account = append_vec.get_account(offset).map(|(_, next_offset)| {
             let account = StoredAccountMetaHandle::new(append_vec, offset);
             offset = next_offset;
             account
-        })
+        });
+        if account.is_none() && !append_vec.is_end(offset) {
+            warn!(
+                "Accounts of AppendVec {}.{} stop parsing at offset {offset}, before its end",
+                append_vec.slot(),
+                append_vec.id()
+            );
+        }
+        account
     })
     .take_while(|account| account.is_some())
-    .filter_map(|account| account)
+    .flatten()
 }
 
 pub struct StoredAccountMetaHandle<'a> {
@@ -91,7 +266,7 @@ pub trait ReadProgressTracking {
     ) -> SnapshotResult<Box<dyn Read>>;
 }
 
-struct NoopReadProgressTracking {}
+pub struct NoopReadProgressTracking {}
 
 impl ReadProgressTracking for NoopReadProgressTracking {
     fn new_read_progress_tracker(
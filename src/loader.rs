@@ -0,0 +1,156 @@
+//! Snapshot sources supported on the command line.
+
+use {
+    crate::{
+        append_vec::AccountsFileFormat, archived::ArchiveSnapshotExtractor,
+        compact::CompactedSnapshotExtractor, manifest::SnapshotManifest, metrics::MetricsReader,
+        progress::LoadProgressTracking, unpacked::UnpackedSnapshotExtractor, AppendVecIterator,
+        ReadProgressTracking, SnapshotError, SnapshotExtractor, SnapshotResult,
+    },
+    indicatif::MultiProgress,
+    log::info,
+    reqwest::blocking::Response,
+    std::{borrow::Cow, fs::File, io::Read, path::Path},
+};
+
+/// Body of a snapshot download. The blocking HTTP client can't be used or dropped
+/// in an async context, where AppendVecs are read.
+struct DownloadBody(Option<Response>);
+
+impl Read for DownloadBody {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let resp = self.0.as_mut().expect("not dropped");
+        tokio::task::block_in_place(|| resp.read(buf))
+    }
+}
+
+impl Drop for DownloadBody {
+    fn drop(&mut self) {
+        let resp = self.0.take();
+        tokio::task::block_in_place(|| drop(resp));
+    }
+}
+
+pub enum SupportedLoader {
+    Unpacked(UnpackedSnapshotExtractor),
+    Compacted(CompactedSnapshotExtractor),
+    ArchiveFile(ArchiveSnapshotExtractor<MetricsReader<File>>),
+    ArchiveDownload(ArchiveSnapshotExtractor<Box<dyn Read>>),
+}
+
+impl SupportedLoader {
+    /// Opens an unpacked or compacted snapshot directory, an archive file, or an HTTP link,
+    /// showing the progress of loading on `progress`. The format of storage files is detected
+    /// unless `storage_format` is set, those of compacted snapshots are always in the AppendVec layout.
+    /// Must be called within a multi-threaded Tokio runtime.
+    pub fn new(
+        source: &str,
+        storage_format: Option<AccountsFileFormat>,
+        progress: &MultiProgress,
+    ) -> anyhow::Result<Self> {
+        if source.starts_with("http://") || source.starts_with("https://") {
+            let progress_tracking = LoadProgressTracking::new(progress.clone(), "download");
+            Self::new_download(source, storage_format, &progress_tracking)
+        } else {
+            let progress_tracking =
+                Box::new(LoadProgressTracking::new(progress.clone(), "manifest"));
+            Self::new_file(source.as_ref(), storage_format, progress_tracking).map_err(Into::into)
+        }
+    }
+
+    fn new_download(
+        url: &str,
+        storage_format: Option<AccountsFileFormat>,
+        progress_tracking: &dyn ReadProgressTracking,
+    ) -> anyhow::Result<Self> {
+        let resp = reqwest::blocking::get(url)?.error_for_status()?;
+        let len = resp.content_length().unwrap_or(0);
+        let loader = ArchiveSnapshotExtractor::from_reader_with_progress(
+            MetricsReader::new(DownloadBody(Some(resp))),
+            url.as_ref(),
+            len,
+            progress_tracking,
+        )?
+        .with_storage_format(storage_format);
+        info!("Streaming snapshot from HTTP");
+        Ok(Self::ArchiveDownload(loader))
+    }
+
+    fn new_file(
+        path: &Path,
+        storage_format: Option<AccountsFileFormat>,
+        progress_tracking: Box<dyn ReadProgressTracking>,
+    ) -> SnapshotResult<Self> {
+        Ok(if CompactedSnapshotExtractor::is_compacted(path) {
+            info!("Reading compacted snapshot");
+            Self::Compacted(CompactedSnapshotExtractor::open(path)?)
+        } else if path.is_dir() {
+            info!("Reading unpacked snapshot");
+            Self::Unpacked(
+                UnpackedSnapshotExtractor::open(path, progress_tracking)?
+                    .with_storage_format(storage_format),
+            )
+        } else {
+            info!("Reading snapshot archive");
+            let file = File::open(path).map_err(|source| SnapshotError::FileError {
+                path: path.to_path_buf(),
+                source,
+            })?;
+            Self::ArchiveFile(
+                ArchiveSnapshotExtractor::from_reader(MetricsReader::new(file))?
+                    .with_storage_format(storage_format),
+            )
+        })
+    }
+
+    /// Bytes of accounts to process. Compacted snapshots are smaller than their manifest.
+    pub fn accounts_len(&self) -> u64 {
+        match self {
+            SupportedLoader::Compacted(loader) => loader
+                .index()
+                .files
+                .iter()
+                .map(|file| file.len as u64)
+                .sum(),
+            _ => self.manifest().accounts_len(),
+        }
+    }
+}
+
+impl SnapshotExtractor for SupportedLoader {
+    fn iter(&mut self) -> AppendVecIterator<'_> {
+        match self {
+            SupportedLoader::Unpacked(loader) => Box::new(loader.iter()),
+            SupportedLoader::Compacted(loader) => loader.iter(),
+            SupportedLoader::ArchiveFile(loader) => Box::new(loader.iter()),
+            SupportedLoader::ArchiveDownload(loader) => Box::new(loader.iter()),
+        }
+    }
+
+    fn manifest(&self) -> &SnapshotManifest {
+        match self {
+            SupportedLoader::Unpacked(loader) => loader.manifest(),
+            SupportedLoader::Compacted(loader) => loader.manifest(),
+            SupportedLoader::ArchiveFile(loader) => loader.manifest(),
+            SupportedLoader::ArchiveDownload(loader) => loader.manifest(),
+        }
+    }
+
+    fn status_cache(&self) -> SnapshotResult<Cow<'_, [u8]>> {
+        match self {
+            SupportedLoader::Unpacked(loader) => loader.status_cache(),
+            SupportedLoader::Compacted(loader) => loader.status_cache(),
+            SupportedLoader::ArchiveFile(loader) => loader.status_cache(),
+            SupportedLoader::ArchiveDownload(loader) => loader.status_cache(),
+        }
+    }
+
+    fn version(&self) -> Option<&str> {
+        match self {
+            SupportedLoader::Unpacked(loader) => loader.version(),
+            SupportedLoader::Compacted(loader) => loader.version(),
+            SupportedLoader::ArchiveFile(loader) => loader.version(),
+            SupportedLoader::ArchiveDownload(loader) => loader.version(),
+        }
+    }
+}
@@ -0,0 +1,344 @@
+use {
+    crate::{
+        solana::{
+            deserialize_from, AccountsDbFields, BankHashInfo, DeserializableVersionedBank,
+            ExtraFields, SerdeAccountsLtHash, SerializableAccountStorageEntry,
+        },
+        ManifestSection, PathContext, SnapshotError, SnapshotResult,
+    },
+    bincode::serialized_size,
+    log::info,
+    serde::de::DeserializeOwned,
+    solana_accounts_db::{
+        accounts_hash::{AccountsHash, AccountsHashKind},
+        epoch_accounts_hash::EpochAccountsHash,
+    },
+    solana_runtime::{
+        serde_snapshot::BankIncrementalSnapshotPersistence, snapshot_hash::SnapshotHash,
+        snapshot_utils::SnapshotVersion,
+    },
+    solana_sdk::{
+        clock::Slot,
+        hash::{extend_and_hash, hashv, Hash},
+    },
+    std::{
+        collections::HashMap,
+        io::{self, Read, Write},
+        ops::Range,
+        path::Path,
+        str::FromStr,
+        time::Instant,
+    },
+};
+
+/// Snapshot manifest, the `snapshots/<slot>/<slot>` file of a snapshot.
+///
+/// The serialized manifest is kept, so that it can be written back unchanged
+/// or with different AppendVec storage entries.
+pub struct SnapshotManifest {
+    pub version: SnapshotVersion,
+    pub bank: DeserializableVersionedBank,
+    pub accounts_db_fields: AccountsDbFields<SerializableAccountStorageEntry>,
+    pub extra_fields: ExtraFields,
+    raw: Vec<u8>,
+    /// Position of the accounts DB fields in `raw`.
+    accounts_db_fields_range: Range<usize>,
+    /// Position of the extra fields in `raw`.
+    extra_fields_range: Range<usize>,
+}
+
+/// Accounts replacing the accounts of a snapshot, see [`SnapshotManifest::with_accounts`].
+pub struct ManifestAccounts {
+    /// AppendVec storage entries of the accounts.
+    pub storages: HashMap<Slot, Vec<SerializableAccountStorageEntry>>,
+    /// Lamports of all accounts.
+    pub capitalization: u64,
+    /// Data length of all accounts.
+    pub accounts_data_len: u64,
+    /// Merkle root of the hashes of the accounts stored at the bank slot.
+    pub accounts_delta_hash: Hash,
+    /// Merkle root of the hashes of all accounts.
+    pub accounts_hash: Hash,
+}
+
+impl SnapshotManifest {
+    /// Reads the manifest at `path` from `reader`, in the layout of snapshot `version`,
+    /// the contents of the snapshot version file. See [`parse_snapshot_version`].
+    pub fn read_from<R: Read>(
+        mut reader: R,
+        path: &Path,
+        version: Option<&str>,
+    ) -> SnapshotResult<Self> {
+        let mut raw = Vec::new();
+        reader.read_to_end(&mut raw).path_context(path)?;
+        Self::from_bytes(raw, version)
+    }
+
+    pub fn from_bytes(raw: Vec<u8>, version: Option<&str>) -> SnapshotResult<Self> {
+        match parse_snapshot_version(version)? {
+            SnapshotVersion::V1_2_0 => Self::from_bytes_v1_2_0(raw),
+        }
+    }
+
+    fn from_bytes_v1_2_0(raw: Vec<u8>) -> SnapshotResult<Self> {
+        let mut rest = &raw[..];
+        let pre_unpack = Instant::now();
+        let bank: DeserializableVersionedBank =
+            deserialize_section(&raw, &mut rest, ManifestSection::BankFields)?;
+        let versioned_bank_post_time = Instant::now();
+        let start = raw.len() - rest.len();
+
+        let accounts_db_fields: AccountsDbFields<SerializableAccountStorageEntry> =
+            deserialize_section(&raw, &mut rest, ManifestSection::AccountsDbFields)?;
+        let accounts_db_fields_post_time = Instant::now();
+        let end = raw.len() - rest.len();
+
+        let extra_fields: ExtraFields =
+            deserialize_section(&raw, &mut rest, ManifestSection::ExtraFields)?;
+        let extra_fields_end = raw.len() - rest.len();
+
+        info!(
+            "Read bank fields in {:?}",
+            versioned_bank_post_time - pre_unpack
+        );
+        info!(
+            "Read accounts DB fields in {:?}",
+            accounts_db_fields_post_time - versioned_bank_post_time
+        );
+
+        Ok(Self {
+            version: SnapshotVersion::V1_2_0,
+            bank,
+            accounts_db_fields,
+            extra_fields,
+            raw,
+            accounts_db_fields_range: start..end,
+            extra_fields_range: end..extra_fields_end,
+        })
+    }
+
+    /// Looks up the storage entry of the AppendVec `<slot>.<id>`.
+    pub fn storage_entry(&self, slot: Slot, id: u64) -> Option<&SerializableAccountStorageEntry> {
+        self.accounts_db_fields
+            .0
+            .get(&slot)?
+            .iter()
+            .find(|entry| entry.id as u64 == id)
+    }
+
+    /// Total `accounts_current_len` of the AppendVecs, the bytes of accounts to process.
+    pub fn accounts_len(&self) -> u64 {
+        self.accounts_db_fields
+            .0
+            .values()
+            .flatten()
+            .map(|entry| entry.accounts_current_len as u64)
+            .sum()
+    }
+
+    /// Writes the manifest as it was read.
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(&self.raw)
+    }
+
+    /// Writes the manifest with its AppendVec storage entries replaced by `storages`.
+    /// All other fields are written as they were read.
+    pub fn write_with_storages<W: Write>(
+        &self,
+        mut writer: W,
+        storages: HashMap<Slot, Vec<SerializableAccountStorageEntry>>,
+    ) -> io::Result<()> {
+        let AccountsDbFields(_, write_version, slot, bank_hash_info, roots, roots_with_hash) =
+            &self.accounts_db_fields;
+        let accounts_db_fields = AccountsDbFields(
+            storages,
+            *write_version,
+            *slot,
+            bank_hash_info.clone(),
+            roots.clone(),
+            roots_with_hash.clone(),
+        );
+        writer.write_all(&self.raw[..self.accounts_db_fields_range.start])?;
+        bincode::serialize_into(&mut writer, &accounts_db_fields)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        writer.write_all(&self.raw[self.accounts_db_fields_range.end..])?;
+        Ok(())
+    }
+
+    /// Manifest of the same bank holding `accounts` instead of the accounts of the snapshot.
+    ///
+    /// The capitalization, accounts data length, accounts hashes and bank hash are recomputed
+    /// the way a validator verifies them when booting from the snapshot. The epoch accounts
+    /// hash, if any, is replaced by the new accounts hash. The accounts lt hash and the
+    /// incremental snapshot persistence are cleared, validators recompute them.
+    pub fn with_accounts(&self, accounts: ManifestAccounts) -> SnapshotResult<Self> {
+        let bank = &self.bank;
+        let AccountsDbFields(_, write_version, slot, bank_hash_info, roots, roots_with_hash) =
+            &self.accounts_db_fields;
+        let source_epoch_accounts_hash = self.extra_fields.epoch_accounts_hash;
+        // The epoch accounts hash is only mixed into the bank hash of the bank ending the
+        // epoch accounts hash window, find out from the source bank hash.
+        let mixes_epoch_accounts_hash = if self.bank_hash(&bank_hash_info.hash, None) == bank.hash {
+            false
+        } else if source_epoch_accounts_hash.is_some()
+            && self.bank_hash(&bank_hash_info.hash, source_epoch_accounts_hash.as_ref())
+                == bank.hash
+        {
+            true
+        } else {
+            return Err(SnapshotError::UnverifiedBankHash(bank.slot));
+        };
+        let epoch_accounts_hash = source_epoch_accounts_hash.map(|_| accounts.accounts_hash);
+        let hash = self.bank_hash(
+            &accounts.accounts_delta_hash,
+            epoch_accounts_hash
+                .as_ref()
+                .filter(|_| mixes_epoch_accounts_hash),
+        );
+
+        let write_error = |section, source| SnapshotError::ManifestWriteError { section, source };
+        let bank_error = |source| write_error(ManifestSection::BankFields, source);
+        let mut raw = self.raw[..self.accounts_db_fields_range.start].to_vec();
+        // The patched bank fields follow variable length fields, locate them by serializing
+        // the fields before them.
+        let hash_offset =
+            serialized_size(&(&bank.blockhash_queue, &bank.ancestors)).map_err(bank_error)?;
+        let capitalization_offset = hash_offset
+            + serialized_size(&(
+                bank.hash,
+                bank.parent_hash,
+                bank.parent_slot,
+                &bank.hard_forks,
+                bank.transaction_count,
+                bank.tick_height,
+                bank.signature_count,
+            ))
+            .map_err(bank_error)?;
+        let accounts_data_len_offset = capitalization_offset
+            + serialized_size(&(
+                bank.capitalization,
+                bank.max_tick_height,
+                bank.hashes_per_tick,
+                bank.ticks_per_slot,
+                bank.ns_per_slot,
+                bank.genesis_creation_time,
+                bank.slots_per_year,
+            ))
+            .map_err(bank_error)?;
+        patch_bank_field(&mut raw, hash_offset, bank.hash.as_ref(), hash.as_ref())?;
+        patch_bank_field(
+            &mut raw,
+            capitalization_offset,
+            &bank.capitalization.to_le_bytes(),
+            &accounts.capitalization.to_le_bytes(),
+        )?;
+        patch_bank_field(
+            &mut raw,
+            accounts_data_len_offset,
+            &bank.accounts_data_len.to_le_bytes(),
+            &accounts.accounts_data_len.to_le_bytes(),
+        )?;
+
+        let accounts_db_fields = AccountsDbFields(
+            accounts.storages,
+            *write_version,
+            *slot,
+            BankHashInfo {
+                hash: accounts.accounts_delta_hash,
+                snapshot_hash: accounts.accounts_hash,
+                stats: bank_hash_info.stats.clone(),
+            },
+            roots.clone(),
+            roots_with_hash.clone(),
+        );
+        bincode::serialize_into(&mut raw, &accounts_db_fields)
+            .map_err(|source| write_error(ManifestSection::AccountsDbFields, source))?;
+        // Serialized in the field order of `ExtraFields`.
+        let extra_fields = (
+            self.extra_fields.lamports_per_signature,
+            None::<BankIncrementalSnapshotPersistence>,
+            epoch_accounts_hash,
+            &self.extra_fields.versioned_epoch_stakes,
+            None::<SerdeAccountsLtHash>,
+        );
+        bincode::serialize_into(&mut raw, &extra_fields)
+            .map_err(|source| write_error(ManifestSection::ExtraFields, source))?;
+        raw.extend_from_slice(&self.raw[self.extra_fields_range.end..]);
+        Self::from_bytes_v1_2_0(raw)
+    }
+
+    /// Bank hash of the bank with `accounts_delta_hash`, mixing in `epoch_accounts_hash`,
+    /// as computed by `Bank::hash_internal_state`.
+    fn bank_hash(&self, accounts_delta_hash: &Hash, epoch_accounts_hash: Option<&Hash>) -> Hash {
+        let bank = &self.bank;
+        let mut hash = hashv(&[
+            bank.parent_hash.as_ref(),
+            accounts_delta_hash.as_ref(),
+            &bank.signature_count.to_le_bytes(),
+            bank.blockhash_queue.last_hash().as_ref(),
+        ]);
+        if let Some(epoch_accounts_hash) = epoch_accounts_hash {
+            hash = hashv(&[hash.as_ref(), epoch_accounts_hash.as_ref()]);
+        }
+        if let Some(buf) = bank.hard_forks.get_hash_data(bank.slot, bank.parent_slot) {
+            hash = extend_and_hash(&hash, &buf);
+        }
+        hash
+    }
+
+    /// Hash in the archive name of the snapshot, `snapshot-<slot>-<hash>.tar.zst`.
+    pub fn snapshot_hash(&self) -> Hash {
+        let accounts_hash = AccountsHash(self.accounts_db_fields.3.snapshot_hash);
+        let epoch_accounts_hash = self
+            .extra_fields
+            .epoch_accounts_hash
+            .map(EpochAccountsHash::new);
+        SnapshotHash::new(
+            &AccountsHashKind::Full(accounts_hash),
+            epoch_accounts_hash.as_ref(),
+        )
+        .0
+    }
+}
+
+/// Overwrites the bank field at `offset` of the manifest, checking that it holds `old`.
+fn patch_bank_field(raw: &mut [u8], offset: u64, old: &[u8], new: &[u8]) -> SnapshotResult<()> {
+    let offset = offset as usize;
+    match raw.get_mut(offset..offset + old.len()) {
+        Some(field) if field == old => {
+            field.copy_from_slice(new);
+            Ok(())
+        }
+        _ => Err(SnapshotError::ManifestError {
+            section: ManifestSection::BankFields,
+            offset,
+            source: Box::new(bincode::ErrorKind::Custom(
+                "unexpected bank field layout".to_owned(),
+            )),
+        }),
+    }
+}
+
+/// Deserializes the manifest section at the start of `rest`, a suffix of `raw`.
+fn deserialize_section<T: DeserializeOwned>(
+    raw: &[u8],
+    rest: &mut &[u8],
+    section: ManifestSection,
+) -> SnapshotResult<T> {
+    let offset = raw.len() - rest.len();
+    deserialize_from(rest).map_err(|source| SnapshotError::ManifestError {
+        section,
+        offset,
+        source,
+    })
+}
+
+/// Parses the contents of a snapshot version file.
+/// Snapshots without a version file are assumed to be version 1.2.0.
+pub fn parse_snapshot_version(version: Option<&str>) -> SnapshotResult<SnapshotVersion> {
+    match version {
+        None => Ok(SnapshotVersion::default()),
+        Some(version) => SnapshotVersion::from_str(version)
+            .map_err(|_| SnapshotError::UnsupportedSnapshotVersion(version.to_owned())),
+    }
+}
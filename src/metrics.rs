@@ -0,0 +1,202 @@
+//! Prometheus metrics of snapshot extraction.
+//!
+//! Metrics are recorded in a process-wide registry by [`par_iter_append_vecs`](crate::parallel::par_iter_append_vecs),
+//! [`par_process_append_vecs`](crate::parallel::par_process_append_vecs) and callers,
+//! and can be served over HTTP with [`serve_metrics`].
+
+use {
+    hyper::{
+        header::CONTENT_TYPE,
+        service::{make_service_fn, service_fn},
+        Body, Method, Request, Response, Server, StatusCode,
+    },
+    prometheus::{
+        core::Collector, Encoder, Gauge, HistogramOpts, HistogramVec, IntCounter, IntCounterVec,
+        IntGauge, Opts, Registry, TextEncoder,
+    },
+    std::{
+        convert::Infallible,
+        future::Future,
+        io::{self, Read},
+        net::SocketAddr,
+        sync::{Mutex, OnceLock},
+        time::Instant,
+    },
+};
+
+pub struct Metrics {
+    registry: Registry,
+    /// Accounts processed, counted by callers and by [`AccountProcessor`](crate::accounts::AccountProcessor).
+    pub accounts: IntCounter,
+    /// Accounts not matching the filter or dropped by a transform of an
+    /// [`AccountProcessor`](crate::accounts::AccountProcessor).
+    pub filtered_accounts: IntCounter,
+    /// AppendVecs processed.
+    pub append_vecs: IntCounter,
+    /// AppendVec bytes, up to `accounts_current_len`, processed. For archives these are
+    /// decompressed bytes.
+    pub append_vec_bytes: IntCounter,
+    /// AppendVecs skipped because they couldn't be read.
+    pub skipped_append_vecs: IntCounter,
+    /// Bytes read from the snapshot source, see [`MetricsReader`].
+    pub source_bytes: IntCounter,
+    /// AppendVecs read from the source and not yet processed by a consumer.
+    pub queue_depth: IntGauge,
+    /// Time consumers take to process an AppendVec, or output sinks an output, by consumer.
+    pub sink_latency: HistogramVec,
+    /// Errors returned by consumers, by consumer.
+    pub sink_errors: IntCounterVec,
+    /// AppendVec bytes of the snapshot, from the manifest.
+    pub total_append_vec_bytes: IntGauge,
+    eta: Gauge,
+    /// Start of processing, set with the total size.
+    start: Mutex<Option<Instant>>,
+}
+
+impl Metrics {
+    fn new() -> prometheus::Result<Self> {
+        let registry = Registry::new_custom(Some("snapshot_etl".to_owned()), None)?;
+        let metrics = Self {
+            accounts: IntCounter::new("accounts_total", "Accounts processed")?,
+            filtered_accounts: IntCounter::new(
+                "filtered_accounts_total",
+                "Accounts filtered out before reaching the sink",
+            )?,
+            append_vecs: IntCounter::new("append_vecs_total", "AppendVecs processed")?,
+            append_vec_bytes: IntCounter::new(
+                "append_vec_bytes_total",
+                "AppendVec bytes processed, decompressed for archives",
+            )?,
+            skipped_append_vecs: IntCounter::new(
+                "skipped_append_vecs_total",
+                "AppendVecs skipped because they couldn't be read",
+            )?,
+            source_bytes: IntCounter::new(
+                "source_bytes_total",
+                "Bytes read from the snapshot source",
+            )?,
+            queue_depth: IntGauge::new(
+                "queue_depth",
+                "AppendVecs read from the source and not yet processed",
+            )?,
+            sink_latency: HistogramVec::new(
+                HistogramOpts::new(
+                    "sink_latency_seconds",
+                    "Time consumers take to process an AppendVec",
+                )
+                .buckets(prometheus::exponential_buckets(0.0001, 4.0, 10)?),
+                &["sink"],
+            )?,
+            sink_errors: IntCounterVec::new(
+                Opts::new("sink_errors_total", "Errors returned by consumers"),
+                &["sink"],
+            )?,
+            total_append_vec_bytes: IntGauge::new(
+                "total_append_vec_bytes",
+                "AppendVec bytes of the snapshot, from the manifest",
+            )?,
+            eta: Gauge::new(
+                "eta_seconds",
+                "Estimated time until all AppendVecs are processed",
+            )?,
+            start: Mutex::new(None),
+            registry,
+        };
+        let collectors: [Box<dyn Collector>; 11] = [
+            Box::new(metrics.accounts.clone()),
+            Box::new(metrics.filtered_accounts.clone()),
+            Box::new(metrics.append_vecs.clone()),
+            Box::new(metrics.append_vec_bytes.clone()),
+            Box::new(metrics.skipped_append_vecs.clone()),
+            Box::new(metrics.source_bytes.clone()),
+            Box::new(metrics.queue_depth.clone()),
+            Box::new(metrics.sink_latency.clone()),
+            Box::new(metrics.sink_errors.clone()),
+            Box::new(metrics.total_append_vec_bytes.clone()),
+            Box::new(metrics.eta.clone()),
+        ];
+        for collector in collectors {
+            metrics.registry.register(collector)?;
+        }
+        Ok(metrics)
+    }
+
+    /// Sets the total AppendVec size of the snapshot the ETA is derived from,
+    /// see [`SnapshotManifest::accounts_len`](crate::manifest::SnapshotManifest::accounts_len), and starts the clock.
+    pub fn start(&self, total_append_vec_bytes: u64) {
+        self.total_append_vec_bytes
+            .set(total_append_vec_bytes as i64);
+        *self.start.lock().unwrap() = Some(Instant::now());
+    }
+
+    /// Estimated seconds until all AppendVecs are processed, from the rate so far.
+    pub fn eta(&self) -> Option<f64> {
+        let start = (*self.start.lock().unwrap())?;
+        let done = self.append_vec_bytes.get();
+        let total = self.total_append_vec_bytes.get() as u64;
+        if done == 0 {
+            return None;
+        }
+        let elapsed = start.elapsed().as_secs_f64();
+        Some(elapsed * total.saturating_sub(done) as f64 / done as f64)
+    }
+
+    /// Encodes all metrics in the Prometheus text format.
+    pub fn encode(&self) -> prometheus::Result<Vec<u8>> {
+        self.eta.set(self.eta().unwrap_or(f64::NAN));
+        let mut buf = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buf)?;
+        Ok(buf)
+    }
+}
+
+/// Returns the process-wide metrics.
+pub fn metrics() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(|| Metrics::new().expect("valid metrics"))
+}
+
+/// Counts the bytes read from a snapshot source in [`Metrics::source_bytes`].
+pub struct MetricsReader<R> {
+    inner: R,
+}
+
+impl<R: Read> MetricsReader<R> {
+    pub const fn new(inner: R) -> Self {
+        Self { inner }
+    }
+}
+
+impl<R: Read> Read for MetricsReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        metrics().source_bytes.inc_by(n as u64);
+        Ok(n)
+    }
+}
+
+/// Binds `addr` and returns a server of the metrics at `http://<addr>/metrics`.
+/// Must be called within a Tokio runtime.
+pub fn serve_metrics(addr: SocketAddr) -> hyper::Result<impl Future<Output = hyper::Result<()>>> {
+    let make_service =
+        make_service_fn(|_| async { Ok::<_, Infallible>(service_fn(handle_request)) });
+    Ok(Server::try_bind(&addr)?.serve(make_service))
+}
+
+async fn handle_request(request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let response = if request.method() != Method::GET || request.uri().path() != "/metrics" {
+        Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty())
+    } else {
+        match metrics().encode() {
+            Ok(body) => Response::builder()
+                .header(CONTENT_TYPE, TextEncoder::new().format_type())
+                .body(Body::from(body)),
+            Err(err) => Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(err.to_string())),
+        }
+    };
+    Ok(response.expect("valid response"))
+}
@@ -1,38 +1,500 @@
 use {
-    crate::{AppendVec, AppendVecIterator},
-    tokio::task::JoinSet,
+    crate::{
+        manifest::SnapshotManifest, metrics::metrics, verify_append_vec, AppendVec,
+        AppendVecIterator, SnapshotError,
+    },
+    log::warn,
+    serde::Serialize,
+    solana_sdk::{clock::Slot, hash::Hash},
+    std::{collections::BTreeMap, path::PathBuf, str::FromStr, time::Instant},
+    tokio::{
+        runtime::{Handle, RuntimeFlavor},
+        sync::mpsc,
+        task::JoinSet,
+    },
 };
 
+/// Snapshot being processed, passed to consumers when they start.
+#[derive(Clone, Copy, Debug)]
+pub struct SnapshotInfo {
+    pub slot: Slot,
+    /// Bank hash.
+    pub hash: Hash,
+    /// AppendVec bytes to process, see [`SnapshotManifest::accounts_len`].
+    pub accounts_len: u64,
+}
+
+impl SnapshotInfo {
+    pub fn new(manifest: &SnapshotManifest) -> Self {
+        Self {
+            slot: manifest.bank.slot,
+            hash: manifest.bank.hash,
+            accounts_len: manifest.accounts_len(),
+        }
+    }
+}
+
+/// Async consumer of AppendVecs, run as Tokio tasks by [`par_iter_append_vecs`].
+/// CPU-bound work should go to an [`AppendVecProcessor`] instead.
+///
+/// Consumers are created as needed. [`on_start`](Self::on_start) is awaited before their first AppendVec,
+/// and [`on_finish`](Self::on_finish) once all AppendVecs were processed, but not if processing failed.
 #[async_trait::async_trait]
 pub trait AppendVecConsumer {
+    async fn on_start(&mut self, _info: &SnapshotInfo) -> anyhow::Result<()> {
+        Ok(())
+    }
+
     async fn on_append_vec(&mut self, append_vec: AppendVec) -> anyhow::Result<()>;
+
+    /// Flushes buffers, commits transactions or closes files.
+    async fn on_finish(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// CPU-bound consumer of AppendVecs, such as account parsing, hashing or encoding.
+/// Run on Tokio's blocking thread pool by [`par_process_append_vecs`], so that it doesn't starve async tasks.
+///
+/// Hooks are called like those of [`AppendVecConsumer`], on the blocking thread pool.
+pub trait AppendVecProcessor {
+    /// Result of processing an AppendVec, passed on to the [`OutputSink`].
+    type Output;
+
+    fn on_start(&mut self, _info: &SnapshotInfo) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn process(&mut self, append_vec: AppendVec) -> anyhow::Result<Self::Output>;
+
+    fn on_finish(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// Async consumer of the outputs of [`AppendVecProcessor`]s, for I/O such as writing files or sending
+/// to a network sink. Runs as a single Tokio task while processors work on the next AppendVecs.
+///
+/// [`on_start`](Self::on_start) is awaited before the first output, and [`on_finish`](Self::on_finish)
+/// after the processors finished and all outputs were consumed, but not if processing failed.
+#[async_trait::async_trait]
+pub trait OutputSink<T> {
+    async fn on_start(&mut self, _info: &SnapshotInfo) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn on_output(&mut self, output: T) -> anyhow::Result<()>;
+
+    async fn on_finish(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// Sink of processors without output, or whose output is not needed.
+pub struct DiscardOutput;
+
+#[async_trait::async_trait]
+impl<T: Send + 'static> OutputSink<T> for DiscardOutput {
+    async fn on_output(&mut self, _output: T) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// What to do with AppendVecs that can't be read.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OnError {
+    /// Fail on the first bad AppendVec.
+    #[default]
+    Abort,
+    /// Verify the accounts of every AppendVec before consuming it, and skip bad ones.
+    /// Only recoverable errors are skipped, see [`SnapshotError::is_recoverable`].
+    Skip,
+}
+
+impl FromStr for OnError {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "abort" => Ok(Self::Abort),
+            "skip" => Ok(Self::Skip),
+            _ => Err(format!("unknown error policy {s}, expected abort or skip")),
+        }
+    }
+}
+
+/// AppendVec skipped because it couldn't be read.
+#[derive(Clone, Debug, Serialize)]
+pub struct SkippedAppendVec {
+    pub slot: Slot,
+    pub id: u64,
+    /// AppendVec file or archive entry, if known.
+    pub path: Option<PathBuf>,
+    pub reason: String,
+}
+
+impl SkippedAppendVec {
+    fn from_error(err: &SnapshotError) -> Option<Self> {
+        match err {
+            SnapshotError::UnknownAppendVec { slot, id, path } => Some(Self {
+                slot: *slot,
+                id: *id,
+                path: Some(path.clone()),
+                reason: "not in the snapshot manifest".to_owned(),
+            }),
+            SnapshotError::InvalidAppendVec {
+                slot,
+                id,
+                path,
+                source,
+            } => Some(Self {
+                slot: *slot,
+                id: *id,
+                path: Some(path.clone()),
+                reason: source.to_string(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Options of [`par_iter_append_vecs`].
+#[derive(Clone, Copy, Debug)]
+pub struct ParIterConfig {
+    /// AppendVecs decoded in parallel.
+    pub decode_threads: usize,
+    /// Consumers running in parallel.
+    pub sink_threads: usize,
+    /// Maximum AppendVecs read from the source and not yet handed to a consumer.
+    /// Reading pauses when the buffer is full.
+    pub buffer_size: usize,
+    /// Hand AppendVecs to consumers in the order of the source, holding back those decoded early
+    /// in the buffer. `sink_threads` is ignored, a single consumer processes them in that order.
+    /// The order is reproducible across runs but is only the slot order if the source yields
+    /// AppendVecs by slot: unpacked snapshots do, archives yield them in the order of their entries.
+    pub ordered: bool,
+    /// Verify the accounts of every AppendVec while decoding, see [`verify_append_vec`].
+    /// AppendVecs that fail fail the run, or are skipped with [`OnError::Skip`].
+    pub verify: bool,
+    pub on_error: OnError,
+    /// Label of the consumers in the metrics, the name of their type if unset.
+    pub name: Option<&'static str>,
 }
 
+impl ParIterConfig {
+    /// Decodes and consumes with `num_threads` each, buffering up to twice as many AppendVecs.
+    pub const fn new(num_threads: usize) -> Self {
+        Self {
+            decode_threads: num_threads,
+            sink_threads: num_threads,
+            buffer_size: 2 * num_threads,
+            ordered: false,
+            verify: true,
+            on_error: OnError::Abort,
+            name: None,
+        }
+    }
+}
+
+pub struct ParIterOutput<A> {
+    pub consumers: Vec<A>,
+    /// AppendVecs skipped with [`OnError::Skip`], ordered by slot and id.
+    pub skipped: Vec<SkippedAppendVec>,
+}
+
+/// AppendVec read from the source, ready for a consumer unless it was skipped.
+enum Decoded {
+    AppendVec(AppendVec),
+    Skipped(SkippedAppendVec),
+}
+
+/// Decodes AppendVecs and feeds them to up to `sink_threads` consumers running in parallel.
+/// Returns the finished consumers once all AppendVecs were processed, so per-worker state can be merged.
+///
+/// Records AppendVec, queue and consumer metrics, see [`crate::metrics`].
 pub async fn par_iter_append_vecs<F, A>(
     iterator: AppendVecIterator<'_>,
+    info: &SnapshotInfo,
     create_consumer: F,
-    num_threads: usize,
-) -> anyhow::Result<()>
+    config: ParIterConfig,
+) -> anyhow::Result<ParIterOutput<A>>
 where
     F: Fn() -> A,
     A: AppendVecConsumer + Send + 'static,
+{
+    let name = config.name.unwrap_or_else(consumer_name::<A>);
+    let mut output = drive(
+        iterator,
+        info,
+        create_consumer,
+        config,
+        |tasks, mut consumer, start, append_vec| {
+            tasks.spawn(async move {
+                if let Some(info) = start {
+                    consumer.on_start(&info).await?;
+                }
+                let len = append_vec.len() as u64;
+                let start = Instant::now();
+                let result = consumer.on_append_vec(append_vec).await;
+                record_sink(name, start, Some(len), &result);
+                result.map(|()| consumer)
+            });
+        },
+    )
+    .await?;
+    futures::future::try_join_all(
+        output
+            .consumers
+            .iter_mut()
+            .map(|consumer| consumer.on_finish()),
+    )
+    .await?;
+    Ok(output)
+}
+
+/// Decodes AppendVecs and processes them with up to `sink_threads` processors on the blocking thread pool.
+/// Their outputs are sent over a channel of `buffer_size` outputs to `sink`, so that processing and output
+/// overlap. Returns the finished processors and sink once all outputs were consumed.
+///
+/// Records AppendVec, queue and consumer metrics, see [`crate::metrics`].
+pub async fn par_process_append_vecs<F, P, S>(
+    iterator: AppendVecIterator<'_>,
+    info: &SnapshotInfo,
+    create_processor: F,
+    mut sink: S,
+    config: ParIterConfig,
+) -> anyhow::Result<(ParIterOutput<P>, S)>
+where
+    F: Fn() -> P,
+    P: AppendVecProcessor + Send + 'static,
+    P::Output: Send + 'static,
+    S: OutputSink<P::Output> + Send + 'static,
+{
+    let name = config.name.unwrap_or_else(consumer_name::<P>);
+    let sink_name = consumer_name::<S>();
+    let (sender, mut receiver) = mpsc::channel(config.buffer_size.max(1));
+    let sink_info = *info;
+    let sink_task = tokio::spawn(async move {
+        sink.on_start(&sink_info).await?;
+        while let Some(output) = receiver.recv().await {
+            let start = Instant::now();
+            let result = sink.on_output(output).await;
+            record_sink(sink_name, start, None, &result);
+            result?;
+        }
+        Ok::<_, anyhow::Error>(sink)
+    });
+
+    let result = drive(
+        iterator,
+        info,
+        create_processor,
+        config,
+        |tasks, mut processor, start, append_vec| {
+            let sender = sender.clone();
+            tasks.spawn_blocking(move || {
+                if let Some(info) = start {
+                    processor.on_start(&info)?;
+                }
+                let len = append_vec.len() as u64;
+                let start = Instant::now();
+                let result = processor.process(append_vec);
+                record_sink(name, start, Some(len), &result);
+                sender
+                    .blocking_send(result?)
+                    .map_err(|_| anyhow::anyhow!("Output sink stopped"))?;
+                Ok(processor)
+            });
+        },
+    )
+    .await;
+    let result = match result {
+        Ok(output) => finish_processors(output).await,
+        Err(err) => Err(err),
+    };
+    drop(sender);
+    // Report why the sink stopped rather than processors failing to send to it.
+    let mut sink = sink_task.await??;
+    let output = result?;
+    sink.on_finish().await?;
+    Ok((output, sink))
+}
+
+async fn finish_processors<P>(mut output: ParIterOutput<P>) -> anyhow::Result<ParIterOutput<P>>
+where
+    P: AppendVecProcessor + Send + 'static,
 {
     let mut tasks = JoinSet::new();
-    for append_vec in iterator {
-        let mut consumer = if tasks.len() >= num_threads {
-            tasks.join_next().await.expect("checked")??
-        } else {
-            create_consumer()
-        };
-
-        tasks.spawn(async move {
-            consumer.on_append_vec(append_vec?).await?;
-            Ok::<_, anyhow::Error>(consumer)
-        });
+    for mut processor in output.consumers.drain(..) {
+        tasks.spawn_blocking(move || processor.on_finish().map(|()| processor));
     }
     while let Some(result) = tasks.join_next().await {
-        result??;
+        output.consumers.push(result??);
+    }
+    Ok(output)
+}
+
+/// Runs AppendVecs through the decode stage on the blocking thread pool, then hands them to `spawn_consumer` on up to
+/// `sink_threads` consumers, which are returned by the spawned tasks when done.
+/// New consumers are handed the snapshot info to start with.
+async fn drive<F, C, S>(
+    iterator: AppendVecIterator<'_>,
+    info: &SnapshotInfo,
+    create_consumer: F,
+    config: ParIterConfig,
+    spawn_consumer: S,
+) -> anyhow::Result<ParIterOutput<C>>
+where
+    F: Fn() -> C,
+    C: Send + 'static,
+    S: Fn(&mut JoinSet<anyhow::Result<C>>, C, Option<SnapshotInfo>, AppendVec),
+{
+    let metrics = metrics();
+    let decode_threads = config.decode_threads.max(1);
+    let sink_threads = if config.ordered {
+        1
+    } else {
+        config.sink_threads.max(1)
+    };
+    let buffer_size = config.buffer_size.max(1);
+    let on_error = config.on_error;
+    let verify = config.verify;
+
+    let mut iterator = iterator.enumerate().fuse();
+    let mut exhausted = false;
+    let mut decoding = JoinSet::new();
+    // Decoded AppendVecs by position in the source.
+    let mut buffer = BTreeMap::new();
+    let mut next_index = 0;
+    let mut sinks = JoinSet::new();
+    let mut consumers = Vec::new();
+    let mut num_consumers = 0;
+    let mut skipped = Vec::new();
+    loop {
+        while !exhausted
+            && decoding.len() < decode_threads
+            && decoding.len() + buffer.len() < buffer_size
+        {
+            let Some((index, append_vec)) = next_blocking(&mut iterator) else {
+                exhausted = true;
+                break;
+            };
+            match append_vec {
+                Ok(append_vec) => {
+                    decoding.spawn_blocking(move || (index, decode(append_vec, verify, on_error)));
+                }
+                Err(err) if on_error == OnError::Skip && err.is_recoverable() => {
+                    let skipped_vec =
+                        SkippedAppendVec::from_error(&err).expect("recoverable error");
+                    buffer.insert(index, Decoded::Skipped(skipped_vec));
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        while sinks.len() < sink_threads {
+            let decoded = match buffer.first_key_value() {
+                Some((&index, _)) if config.ordered && index != next_index => None,
+                _ => buffer.pop_first(),
+            };
+            let Some((index, decoded)) = decoded else {
+                break;
+            };
+            next_index = index + 1;
+            let append_vec = match decoded {
+                Decoded::AppendVec(append_vec) => append_vec,
+                Decoded::Skipped(skipped_vec) => {
+                    skip(&mut skipped, skipped_vec);
+                    continue;
+                }
+            };
+            let (consumer, start) = match consumers.pop() {
+                Some(consumer) => (consumer, None),
+                None => {
+                    num_consumers += 1;
+                    (create_consumer(), Some(*info))
+                }
+            };
+            spawn_consumer(&mut sinks, consumer, start, append_vec);
+        }
+        metrics
+            .queue_depth
+            .set((decoding.len() + buffer.len() + sinks.len()) as i64);
+
+        tokio::select! {
+            Some(result) = decoding.join_next(), if !decoding.is_empty() => {
+                let (index, decoded) = result?;
+                buffer.insert(index, decoded?);
+            }
+            Some(result) = sinks.join_next(), if !sinks.is_empty() => {
+                consumers.push(result??);
+            }
+            else => break,
+        }
+    }
+    debug_assert!(buffer.is_empty());
+    debug_assert_eq!(consumers.len(), num_consumers);
+    skipped.sort_unstable_by_key(|skipped| (skipped.slot, skipped.id));
+
+    Ok(ParIterOutput { consumers, skipped })
+}
+
+/// Reads the next AppendVec of `iterator`. Extractors read and map AppendVecs in `next()`, which
+/// runs in [`tokio::task::block_in_place`] on a multi-threaded runtime so that other tasks move off
+/// the driver's worker thread. On a current-thread runtime, other tasks wait for the read.
+fn next_blocking<I: Iterator>(iterator: &mut I) -> Option<I::Item> {
+    match Handle::current().runtime_flavor() {
+        RuntimeFlavor::MultiThread => tokio::task::block_in_place(|| iterator.next()),
+        _ => iterator.next(),
+    }
+}
+
+/// Verifies the accounts of an AppendVec if `verify` is set, so that AppendVecs whose
+/// accounts stop parsing before their end fail the run or are skipped.
+fn decode(
+    append_vec: AppendVec,
+    verify: bool,
+    on_error: OnError,
+) -> Result<Decoded, SnapshotError> {
+    if verify {
+        match verify_append_vec(&append_vec) {
+            Ok(_) => {}
+            Err(err) if on_error == OnError::Skip => {
+                let skipped = SkippedAppendVec::from_error(&err).expect("invalid AppendVec");
+                return Ok(Decoded::Skipped(skipped));
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(Decoded::AppendVec(append_vec))
+}
+
+/// Records the time a consumer took to process an AppendVec of `len` bytes, or an output without length.
+fn record_sink<T>(name: &str, start: Instant, len: Option<u64>, result: &anyhow::Result<T>) {
+    let metrics = metrics();
+    metrics
+        .sink_latency
+        .with_label_values(&[name])
+        .observe(start.elapsed().as_secs_f64());
+    if result.is_err() {
+        metrics.sink_errors.with_label_values(&[name]).inc();
+    } else if let Some(len) = len {
+        metrics.append_vecs.inc();
+        metrics.append_vec_bytes.inc_by(len);
     }
+}
+
+fn skip(skipped: &mut Vec<SkippedAppendVec>, append_vec: SkippedAppendVec) {
+    warn!(
+        "Skipping AppendVec {}.{}: {}",
+        append_vec.slot, append_vec.id, append_vec.reason
+    );
+    metrics().skipped_append_vecs.inc();
+    skipped.push(append_vec);
+}
 
-    Ok(())
+/// Name of a consumer type in metrics, without its module path.
+fn consumer_name<A>() -> &'static str {
+    let name = std::any::type_name::<A>();
+    name.rsplit("::").next().unwrap_or(name)
 }
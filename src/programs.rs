@@ -0,0 +1,309 @@
+//! Extraction of deployed BPF programs.
+//!
+//! Upgradeable programs are paired with their ProgramData account, v1/v2 loader programs
+//! are executable accounts holding their ELF directly. Every ELF is written to
+//! `<out_dir>/<program_id>.so`, staged in `<out_dir>/.staging` while the snapshot is read.
+
+use {
+    crate::{
+        accounts::{Account, AccountFilter},
+        versions::LatestVersions,
+        PathContext, SnapshotResult,
+    },
+    log::warn,
+    serde::Serialize,
+    serde_with::{serde_as, DisplayFromStr},
+    sha2::{Digest, Sha256},
+    solana_sdk::{
+        account::ReadableAccount,
+        bpf_loader, bpf_loader_deprecated,
+        bpf_loader_upgradeable::{self, UpgradeableLoaderState},
+        clock::Slot,
+        pubkey::Pubkey,
+    },
+    std::{
+        collections::HashMap,
+        fs, mem,
+        path::{Path, PathBuf},
+    },
+};
+
+/// Directory of the output directory the ELFs are written to until [`ProgramsExtractor::finish`].
+const STAGING_DIR: &str = ".staging";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProgramLoader {
+    /// BPF Loader v1 (`BPFLoader1111111111111111111111111111111111`).
+    Deprecated,
+    /// BPF Loader v2 (`BPFLoader2111111111111111111111111111111111`).
+    V2,
+    /// BPF Loader Upgradeable (`BPFLoaderUpgradeab1e11111111111111111111111`).
+    Upgradeable,
+}
+
+/// Latest version of a program account.
+enum ProgramAccount {
+    /// Upgradeable program and its ProgramData address.
+    Program(Pubkey),
+    ProgramData(ProgramData),
+    /// v1/v2 loader program holding its ELF.
+    Executable(ProgramLoader, StagedElf),
+}
+
+struct ProgramData {
+    deploy_slot: Slot,
+    upgrade_authority: Option<Pubkey>,
+    elf: StagedElf,
+}
+
+/// ELF of a program account version, written to the staging directory when it is found.
+/// The file is removed when dropped, unless it was moved to the output directory.
+struct StagedElf {
+    path: PathBuf,
+    size: u64,
+    sha256: String,
+}
+
+impl StagedElf {
+    fn write(path: PathBuf, elf: &[u8]) -> SnapshotResult<Self> {
+        fs::write(&path, elf).write_context(&path)?;
+        Ok(Self {
+            path,
+            size: elf.len() as u64,
+            sha256: const_hex::encode(Sha256::digest(elf)),
+        })
+    }
+
+    fn persist(mut self, path: &Path) -> SnapshotResult<()> {
+        let staged = mem::take(&mut self.path);
+        fs::rename(staged, path).write_context(path)
+    }
+}
+
+impl Drop for StagedElf {
+    fn drop(&mut self) {
+        if !self.path.as_os_str().is_empty() {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Manifest entry for a single program.
+#[serde_as]
+#[derive(Clone, Debug, Serialize)]
+pub struct ProgramInfo {
+    #[serde_as(as = "DisplayFromStr")]
+    pub program_id: Pubkey,
+    pub loader: ProgramLoader,
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    pub programdata_address: Option<Pubkey>,
+    /// Slot the program was last deployed at, upgradeable programs only.
+    pub deploy_slot: Option<Slot>,
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    pub upgrade_authority: Option<Pubkey>,
+    /// ELF size in bytes, `None` if the ELF was not found (e.g. closed ProgramData).
+    pub size: Option<u64>,
+    pub sha256: Option<String>,
+    /// ELF file name relative to the output directory.
+    pub file: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProgramsManifest {
+    pub slot: Slot,
+    pub programs: Vec<ProgramInfo>,
+}
+
+/// Finds program accounts and writes their ELFs to an output directory.
+///
+/// Every worker uses its own extractor from [`Self::worker`], merged back with [`Self::merge`].
+/// Only the latest version of each program account is tracked, its ELF is written to a staging
+/// directory as it is found and removed once a newer version replaces it. Closed accounts of
+/// any owner are kept as tombstones, as the closing version of a program or ProgramData account
+/// is owned by the system program.
+pub struct ProgramsExtractor {
+    out_dir: PathBuf,
+    staging_dir: PathBuf,
+    accounts: LatestVersions<ProgramAccount>,
+}
+
+impl ProgramsExtractor {
+    pub fn new(out_dir: &Path) -> SnapshotResult<Self> {
+        let staging_dir = out_dir.join(STAGING_DIR);
+        fs::create_dir_all(&staging_dir).write_context(&staging_dir)?;
+        Ok(Self {
+            out_dir: out_dir.to_path_buf(),
+            staging_dir,
+            accounts: LatestVersions::default(),
+        })
+    }
+
+    /// Empty extractor staging ELFs to the same directory.
+    pub fn worker(&self) -> Self {
+        Self {
+            out_dir: self.out_dir.clone(),
+            staging_dir: self.staging_dir.clone(),
+            accounts: LatestVersions::default(),
+        }
+    }
+
+    /// Selects the accounts to process: accounts of the BPF loaders, and closed accounts.
+    pub fn account_filter() -> AccountFilter {
+        AccountFilter::new(
+            [],
+            [
+                bpf_loader_upgradeable::ID,
+                bpf_loader::ID,
+                bpf_loader_deprecated::ID,
+            ],
+        )
+        .with_closed_accounts()
+    }
+
+    pub fn process_account(&mut self, account: &Account) -> SnapshotResult<()> {
+        let Account {
+            slot,
+            write_version,
+            pubkey,
+            ..
+        } = *account;
+        let owner = *account.account.owner();
+        let loader = if owner == bpf_loader_upgradeable::ID {
+            Some(ProgramLoader::Upgradeable)
+        } else if owner == bpf_loader::ID {
+            Some(ProgramLoader::V2)
+        } else if owner == bpf_loader_deprecated::ID {
+            Some(ProgramLoader::Deprecated)
+        } else {
+            None
+        };
+        let closed = account.account.lamports() == 0;
+        if (loader.is_none() && !closed) || !self.accounts.is_newer(&pubkey, slot, write_version) {
+            return Ok(());
+        }
+        let data = account.account.data();
+        let staged_path = self
+            .staging_dir
+            .join(format!("{pubkey}.{slot}.{write_version}"));
+        let value = match loader {
+            _ if closed => None,
+            None => return Ok(()),
+            Some(ProgramLoader::Upgradeable) => {
+                match bincode::deserialize::<UpgradeableLoaderState>(data) {
+                    Ok(UpgradeableLoaderState::Program {
+                        programdata_address,
+                    }) => Some(ProgramAccount::Program(programdata_address)),
+                    Ok(UpgradeableLoaderState::ProgramData {
+                        slot: deploy_slot,
+                        upgrade_authority_address,
+                    }) => {
+                        let elf = data
+                            .get(UpgradeableLoaderState::size_of_programdata_metadata()..)
+                            .unwrap_or_default();
+                        Some(ProgramAccount::ProgramData(ProgramData {
+                            deploy_slot,
+                            upgrade_authority: upgrade_authority_address,
+                            elf: StagedElf::write(staged_path, elf)?,
+                        }))
+                    }
+                    Ok(UpgradeableLoaderState::Buffer { .. })
+                    | Ok(UpgradeableLoaderState::Uninitialized) => None,
+                    Err(err) => {
+                        warn!("Failed to decode upgradeable loader account {pubkey}: {err}");
+                        return Ok(());
+                    }
+                }
+            }
+            Some(loader) if account.account.executable() => Some(ProgramAccount::Executable(
+                loader,
+                StagedElf::write(staged_path, data)?,
+            )),
+            Some(_) => None,
+        };
+        self.accounts.insert(pubkey, slot, write_version, value);
+        Ok(())
+    }
+
+    /// Merges the accounts of another worker, removing the ELFs of the replaced versions.
+    pub fn merge(&mut self, other: ProgramsExtractor) {
+        self.accounts.merge(other.accounts);
+    }
+
+    /// Pairs programs with their ProgramData, moves the ELFs to the output directory and
+    /// returns the manifest.
+    pub fn finish(self, slot: Slot) -> SnapshotResult<ProgramsManifest> {
+        let Self {
+            out_dir,
+            staging_dir,
+            accounts,
+        } = self;
+        let mut programdata = HashMap::new();
+        let mut accounts: Vec<_> = accounts
+            .into_values()
+            .filter_map(|(pubkey, account)| match account {
+                ProgramAccount::ProgramData(data) => {
+                    programdata.insert(pubkey, data);
+                    None
+                }
+                account => Some((pubkey, account)),
+            })
+            .collect();
+        accounts.sort_unstable_by_key(|(program_id, _)| *program_id);
+
+        let mut programs = Vec::with_capacity(accounts.len());
+        for (program_id, account) in accounts {
+            let mut info = ProgramInfo {
+                program_id,
+                loader: ProgramLoader::Upgradeable,
+                programdata_address: None,
+                deploy_slot: None,
+                upgrade_authority: None,
+                size: None,
+                sha256: None,
+                file: None,
+            };
+            let elf = match account {
+                ProgramAccount::Program(programdata_address) => {
+                    info.programdata_address = Some(programdata_address);
+                    match programdata.remove(&programdata_address) {
+                        Some(data) => {
+                            info.deploy_slot = Some(data.deploy_slot);
+                            info.upgrade_authority = data.upgrade_authority;
+                            Some(data.elf)
+                        }
+                        None => {
+                            warn!(
+                                "Missing ProgramData {programdata_address} for program {program_id}"
+                            );
+                            None
+                        }
+                    }
+                }
+                ProgramAccount::Executable(loader, elf) => {
+                    info.loader = loader;
+                    Some(elf)
+                }
+                // Taken out above.
+                ProgramAccount::ProgramData(_) => continue,
+            };
+            if let Some(elf) = elf {
+                let file = elf_file_name(&program_id);
+                info.size = Some(elf.size);
+                info.sha256 = Some(elf.sha256.clone());
+                elf.persist(&out_dir.join(&file))?;
+                info.file = Some(file);
+            }
+            programs.push(info);
+        }
+
+        // ProgramData accounts without a program are not written.
+        drop(programdata);
+        fs::remove_dir_all(&staging_dir).write_context(&staging_dir)?;
+        Ok(ProgramsManifest { slot, programs })
+    }
+}
+
+fn elf_file_name(pubkey: &Pubkey) -> String {
+    format!("{pubkey}.so")
+}
@@ -0,0 +1,147 @@
+//! Progress bars of snapshot loading and account processing.
+
+use {
+    crate::{metrics::metrics, ReadProgressTracking, SnapshotError, SnapshotResult},
+    indicatif::{HumanCount, MultiProgress, ProgressBar, ProgressBarIter, ProgressStyle},
+    std::{
+        io::{IoSliceMut, Read},
+        path::Path,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        thread::{self, JoinHandle},
+        time::Duration,
+    },
+};
+
+/// Interval at which [`AccountsProgress`] is updated from the metrics.
+const TICK: Duration = Duration::from_millis(100);
+
+/// Shows a progress bar of the bytes read from snapshot files, such as the manifest or a download.
+pub struct LoadProgressTracking {
+    progress: MultiProgress,
+    prefix: &'static str,
+}
+
+impl LoadProgressTracking {
+    pub const fn new(progress: MultiProgress, prefix: &'static str) -> Self {
+        Self { progress, prefix }
+    }
+}
+
+impl ReadProgressTracking for LoadProgressTracking {
+    fn new_read_progress_tracker(
+        &self,
+        _path: &Path,
+        rd: Box<dyn Read>,
+        file_len: u64,
+    ) -> SnapshotResult<Box<dyn Read>> {
+        // HTTP sources may not report their length.
+        let progress_bar = match file_len {
+            0 => ProgressBar::new_spinner(),
+            _ => ProgressBar::new(file_len),
+        };
+        let progress_bar = self.progress.add(progress_bar.with_style(
+            ProgressStyle::with_template(
+                "{prefix:>10.bold.dim} {spinner:.green} [{bar:.cyan/blue}] {bytes}/{total_bytes} ({percent}%)",
+            )
+            .map_err(|error| SnapshotError::ReadProgressTracking(error.to_string()))?
+            .progress_chars("#>-"),
+        ));
+        progress_bar.set_prefix(self.prefix);
+        Ok(Box::new(LoadProgressTracker {
+            rd: progress_bar.wrap_read(rd),
+            progress_bar,
+        }))
+    }
+}
+
+struct LoadProgressTracker {
+    progress_bar: ProgressBar,
+    rd: ProgressBarIter<Box<dyn Read>>,
+}
+
+impl Drop for LoadProgressTracker {
+    fn drop(&mut self) {
+        self.progress_bar.finish()
+    }
+}
+
+impl Read for LoadProgressTracker {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.rd.read(buf)
+    }
+
+    fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> std::io::Result<usize> {
+        self.rd.read_vectored(bufs)
+    }
+
+    fn read_to_string(&mut self, buf: &mut String) -> std::io::Result<usize> {
+        self.rd.read_to_string(buf)
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> std::io::Result<()> {
+        self.rd.read_exact(buf)
+    }
+}
+
+/// Progress of the accounts processed, read from the metrics. The progress bar tracks
+/// AppendVec bytes against the total of the snapshot, for a percentage and ETA.
+pub struct AccountsProgress {
+    bar: ProgressBar,
+    stop: Arc<AtomicBool>,
+    ticker: Option<JoinHandle<()>>,
+}
+
+impl AccountsProgress {
+    pub fn new(progress: &MultiProgress, accounts_len: u64) -> anyhow::Result<Self> {
+        let tmpl = ProgressStyle::with_template("{prefix:>10.bold.dim} {spinner:.green} [{bar:30.cyan/blue}] {percent}% eta={eta} rate={binary_bytes_per_sec} {msg} {elapsed_precise:.cyan}")?
+            .progress_chars("#>-");
+        let bar = progress.add(ProgressBar::new(accounts_len).with_style(tmpl));
+        bar.set_prefix("accounts");
+        let stop = Arc::new(AtomicBool::new(false));
+        let ticker = thread::spawn({
+            let bar = bar.clone();
+            let stop = Arc::clone(&stop);
+            move || {
+                while !stop.load(Ordering::Relaxed) {
+                    Self::update(&bar);
+                    thread::park_timeout(TICK);
+                }
+            }
+        });
+        Ok(Self {
+            bar,
+            stop,
+            ticker: Some(ticker),
+        })
+    }
+
+    fn update(bar: &ProgressBar) {
+        let metrics = metrics();
+        bar.set_position(metrics.append_vec_bytes.get());
+        bar.set_message(format!("processed={}", HumanCount(metrics.accounts.get())));
+    }
+
+    fn stop(&mut self) {
+        if let Some(ticker) = self.ticker.take() {
+            self.stop.store(true, Ordering::Relaxed);
+            ticker.thread().unpark();
+            let _ = ticker.join();
+        }
+    }
+
+    /// Shows the final counts and stops updating.
+    pub fn finish(mut self) {
+        self.stop();
+        Self::update(&self.bar);
+        self.bar.finish();
+    }
+}
+
+impl Drop for AccountsProgress {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
@@ -0,0 +1,249 @@
+//! Rent-exemption audit of live accounts.
+
+use {
+    crate::{append_vec::StoredAccountMeta, versions::LatestVersions},
+    serde::Serialize,
+    serde_with::{serde_as, DisplayFromStr},
+    solana_sdk::{
+        clock::{Epoch, Slot},
+        pubkey::Pubkey,
+        rent::RentDue,
+        rent_collector::RentCollector,
+    },
+    std::collections::HashMap,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RentStatus {
+    /// Holds at least the rent-exempt minimum balance, or is not subject to rent.
+    RentExempt,
+    /// Below the rent-exempt minimum, but covers the rent due at the bank epoch.
+    RentPaying,
+    /// Below the rent-exempt minimum and unable to cover the rent due at the bank epoch.
+    BelowMinimum,
+}
+
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub struct RentTotals {
+    pub accounts: u64,
+    pub lamports: u64,
+    pub data_bytes: u64,
+}
+
+impl RentTotals {
+    fn add(&mut self, lamports: u64, data_len: u64) {
+        self.accounts += 1;
+        self.lamports += lamports;
+        self.data_bytes += data_len;
+    }
+
+    fn merge(&mut self, other: &RentTotals) {
+        self.accounts += other.accounts;
+        self.lamports += other.lamports;
+        self.data_bytes += other.data_bytes;
+    }
+}
+
+/// Accounts by rent status.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct RentBreakdown {
+    pub rent_exempt: RentTotals,
+    pub rent_paying: RentTotals,
+    pub below_minimum: RentTotals,
+    /// Lamports missing for non-exempt accounts to reach their rent-exempt minimum.
+    pub shortfall_lamports: u64,
+    /// Rent due by non-exempt accounts at the bank epoch.
+    pub rent_due_lamports: u64,
+}
+
+impl RentBreakdown {
+    fn add(&mut self, account: &LiveAccount, shortfall: u64) {
+        let totals = match account.status {
+            RentStatus::RentExempt => &mut self.rent_exempt,
+            RentStatus::RentPaying => &mut self.rent_paying,
+            RentStatus::BelowMinimum => &mut self.below_minimum,
+        };
+        totals.add(account.lamports, account.data_len.into());
+        self.shortfall_lamports += shortfall;
+        self.rent_due_lamports += account.rent_due;
+    }
+
+    fn merge(&mut self, other: &RentBreakdown) {
+        self.rent_exempt.merge(&other.rent_exempt);
+        self.rent_paying.merge(&other.rent_paying);
+        self.below_minimum.merge(&other.below_minimum);
+        self.shortfall_lamports += other.shortfall_lamports;
+        self.rent_due_lamports += other.rent_due_lamports;
+    }
+
+    const fn non_exempt_accounts(&self) -> u64 {
+        self.rent_paying.accounts + self.below_minimum.accounts
+    }
+}
+
+#[serde_as]
+#[derive(Clone, Debug, Serialize)]
+pub struct OwnerRentAudit {
+    #[serde_as(as = "DisplayFromStr")]
+    pub owner: Pubkey,
+    #[serde(flatten)]
+    pub breakdown: RentBreakdown,
+}
+
+/// Rent audit report of a snapshot.
+#[derive(Clone, Debug, Serialize)]
+pub struct RentAudit {
+    pub slot: Slot,
+    pub epoch: Epoch,
+    pub lamports_per_byte_year: u64,
+    pub exemption_threshold: f64,
+    /// Whether only the latest version of each account was audited, otherwise every stored
+    /// version with lamports was.
+    pub skip_stale: bool,
+    #[serde(flatten)]
+    pub total: RentBreakdown,
+    /// Owner programs, by descending number of non-exempt accounts.
+    pub owners: Vec<OwnerRentAudit>,
+}
+
+/// Rent status of an account version, without its data.
+struct LiveAccount {
+    owner: Pubkey,
+    lamports: u64,
+    rent_due: u64,
+    /// Account data is at most 10 MiB.
+    data_len: u32,
+    status: RentStatus,
+}
+
+/// Classifies accounts against the bank's rent collector and aggregates them by owner.
+/// Collectors of parallel workers can be merged.
+///
+/// Every stored version with lamports is audited, including stale ones, unless stale versions
+/// are skipped. Versions with zero lamports are closed and never audited.
+pub struct RentAuditCollector {
+    rent_collector: RentCollector,
+    total: RentBreakdown,
+    owners: HashMap<Pubkey, RentBreakdown>,
+    /// Rent status of the latest version of every account, only tracked when skipping stale versions.
+    latest: Option<LatestVersions<LiveAccount>>,
+}
+
+impl RentAuditCollector {
+    /// Skipping stale versions requires keeping every account in memory.
+    pub fn new(rent_collector: RentCollector, skip_stale: bool) -> Self {
+        Self {
+            rent_collector,
+            total: RentBreakdown::default(),
+            owners: HashMap::new(),
+            latest: skip_stale.then(LatestVersions::default),
+        }
+    }
+
+    pub fn process_account(&mut self, slot: Slot, account: &StoredAccountMeta) {
+        if let Some(latest) = &mut self.latest {
+            let pubkey = account.meta.pubkey;
+            let write_version = account.meta.write_version_obsolete;
+            if latest.is_newer(&pubkey, slot, write_version) {
+                let live = Self::classify(&self.rent_collector, account);
+                latest.insert(pubkey, slot, write_version, live);
+            }
+        } else if let Some(live) = Self::classify(&self.rent_collector, account) {
+            self.add(&live);
+        }
+    }
+
+    /// Rent status of `account`, `None` if it is closed.
+    fn classify(
+        rent_collector: &RentCollector,
+        account: &StoredAccountMeta,
+    ) -> Option<LiveAccount> {
+        let lamports = account.account_meta.lamports;
+        if lamports == 0 {
+            return None;
+        }
+        let data_len = account.data.len();
+        let (status, rent_due) = if rent_collector
+            .should_collect_rent(&account.meta.pubkey, account.account_meta.executable)
+        {
+            match rent_collector.get_rent_due(lamports, data_len, account.account_meta.rent_epoch) {
+                RentDue::Exempt => (RentStatus::RentExempt, 0),
+                RentDue::Paying(due) if lamports > due => (RentStatus::RentPaying, due),
+                RentDue::Paying(due) => (RentStatus::BelowMinimum, due),
+            }
+        } else {
+            (RentStatus::RentExempt, 0)
+        };
+        Some(LiveAccount {
+            owner: account.account_meta.owner,
+            lamports,
+            rent_due,
+            data_len: data_len as u32,
+            status,
+        })
+    }
+
+    fn add(&mut self, account: &LiveAccount) {
+        let shortfall = if account.status == RentStatus::RentExempt {
+            0
+        } else {
+            self.rent_collector
+                .rent
+                .minimum_balance(account.data_len as usize)
+                .saturating_sub(account.lamports)
+        };
+        self.total.add(account, shortfall);
+        self.owners
+            .entry(account.owner)
+            .or_default()
+            .add(account, shortfall);
+    }
+
+    pub fn merge(&mut self, other: RentAuditCollector) {
+        self.total.merge(&other.total);
+        for (owner, breakdown) in &other.owners {
+            self.owners.entry(*owner).or_default().merge(breakdown);
+        }
+        if let (Some(latest), Some(other)) = (&mut self.latest, other.latest) {
+            latest.merge(other);
+        }
+    }
+
+    pub fn finish(mut self, slot: Slot) -> RentAudit {
+        let skip_stale = self.latest.is_some();
+        if let Some(latest) = self.latest.take() {
+            for (_, account) in latest.into_values() {
+                self.add(&account);
+            }
+        }
+
+        let mut owners: Vec<_> = self
+            .owners
+            .into_iter()
+            .map(|(owner, breakdown)| OwnerRentAudit { owner, breakdown })
+            .collect();
+        owners.sort_unstable_by(|a, b| {
+            b.breakdown
+                .non_exempt_accounts()
+                .cmp(&a.breakdown.non_exempt_accounts())
+                .then(
+                    b.breakdown
+                        .rent_exempt
+                        .accounts
+                        .cmp(&a.breakdown.rent_exempt.accounts),
+                )
+                .then(a.owner.cmp(&b.owner))
+        });
+
+        RentAudit {
+            slot,
+            epoch: self.rent_collector.epoch,
+            lamports_per_byte_year: self.rent_collector.rent.lamports_per_byte_year,
+            exemption_threshold: self.rent_collector.rent.exemption_threshold,
+            skip_stale,
+            total: self.total,
+            owners,
+        }
+    }
+}
@@ -0,0 +1,329 @@
+//! Rewriting a filtered subset of a snapshot's accounts into a snapshot archive validators can boot from.
+
+use {
+    crate::{
+        accounts::AccountFilter,
+        append_vec::{AccountsFileFormat, AppendVec},
+        append_vec_iter,
+        manifest::ManifestAccounts,
+        solana::SerializableAccountStorageEntry,
+        versions::LatestVersions,
+        PathContext, SnapshotExtractor, SnapshotResult, ACCOUNTS_DIR, SNAPSHOTS_DIR, VERSION_FILE,
+    },
+    log::warn,
+    serde::Serialize,
+    solana_accounts_db::{
+        accounts_db::AccountsDb, accounts_file::ALIGN_BOUNDARY_OFFSET,
+        accounts_hash::AccountsHasher, u64_align,
+    },
+    solana_runtime::snapshot_utils::SNAPSHOT_STATUS_CACHE_FILENAME,
+    solana_sdk::clock::Slot,
+    std::{
+        collections::HashMap,
+        fs::{self, File},
+        io::{self, BufWriter, Write},
+        path::{Path, PathBuf},
+        sync::Mutex,
+    },
+    tar::{EntryType, Header},
+};
+
+/// Directory of the staging directory holding the matching versions of accounts,
+/// before stale versions are dropped.
+const STAGED_DIR: &str = "staged";
+
+/// Snapshot version written if the source snapshot has no version file.
+const DEFAULT_SNAPSHOT_VERSION: &str = "1.2.0";
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct RepackSummary {
+    pub slot: Slot,
+    /// Path of the written archive.
+    pub archive: PathBuf,
+    /// Hash in the archive name, `snapshot-<slot>-<hash>.tar.zst`.
+    pub snapshot_hash: String,
+    pub capitalization: u64,
+    pub append_vecs: u64,
+    pub accounts: u64,
+    pub account_bytes: u64,
+}
+
+/// Writes the AppendVecs of a filtered snapshot to a staging directory, then
+/// packs them with the manifest, status cache and version file into a `.tar.zst` archive.
+///
+/// The filter applies to the latest version of each account: stale versions are dropped,
+/// and so are accounts whose latest version is closed. Versions are kept in memory for
+/// the accounts matching the filter and for closed accounts until the archive is written.
+/// A version not matching the filter only hides the staged versions of its account once
+/// one of them has been processed, so an account moved to an owner outside the filter can
+/// be kept with its stale version unless AppendVecs are processed in slot order.
+///
+/// The capitalization, accounts hashes and bank hash of the manifest are recomputed from
+/// the kept accounts, so that validators can boot from the archive as long as the accounts
+/// the runtime reads are kept, see [`AccountFilter::with_runtime_accounts`].
+///
+/// AppendVecs can be processed in parallel. AppendVecs left without accounts are dropped.
+pub struct SnapshotRepacker {
+    filter: AccountFilter,
+    staging_dir: PathBuf,
+    state: Mutex<RepackState>,
+}
+
+#[derive(Default)]
+struct RepackState {
+    /// Slot, id and length of the staged AppendVecs.
+    staged: Vec<(Slot, u64, usize)>,
+    /// Whether the latest version of each tracked account is kept.
+    versions: LatestVersions<()>,
+}
+
+impl SnapshotRepacker {
+    /// AppendVecs are staged in `staging_dir`, which is removed when the archive is written.
+    pub fn new(staging_dir: &Path, filter: AccountFilter) -> io::Result<Self> {
+        fs::create_dir_all(staging_dir.join(STAGED_DIR))?;
+        fs::create_dir_all(staging_dir.join(ACCOUNTS_DIR))?;
+        Ok(Self {
+            filter,
+            staging_dir: staging_dir.to_path_buf(),
+            state: Mutex::new(RepackState::default()),
+        })
+    }
+
+    /// Stages the live versions of `append_vec` matching the filter, and records the
+    /// versions hiding staged ones to drop the stale versions in [`Self::finish`].
+    pub fn process_append_vec(&self, append_vec: &AppendVec) -> io::Result<()> {
+        let (slot, id) = (append_vec.slot(), append_vec.id());
+        let path = self
+            .staging_dir
+            .join(STAGED_DIR)
+            .join(format!("{slot}.{id}"));
+        let mut writer = AccountsWriter::create(&path)?;
+        let mut versions = LatestVersions::default();
+        // Live versions not matching the filter, only tracked for accounts with staged versions.
+        let mut unmatched = Vec::new();
+        for handle in append_vec_iter(append_vec) {
+            let Some(account) = handle.access() else {
+                continue;
+            };
+            let (pubkey, write_version) =
+                (account.meta.pubkey, account.meta.write_version_obsolete);
+            if account.account_meta.lamports == 0 {
+                versions.insert(pubkey, slot, write_version, None);
+            } else if self.filter.matches(&account) {
+                writer.append(&append_vec.account_bytes(&account))?;
+                versions.insert(pubkey, slot, write_version, Some(()));
+            } else {
+                unmatched.push((pubkey, write_version));
+            }
+        }
+        let (len, accounts) = writer.finish()?;
+
+        let mut state = self.state.lock().unwrap();
+        state.versions.merge(versions);
+        for (pubkey, write_version) in unmatched {
+            if state.versions.contains(&pubkey) {
+                state.versions.insert(pubkey, slot, write_version, None);
+            }
+        }
+        if accounts == 0 {
+            return fs::remove_file(path);
+        }
+        state.staged.push((slot, id, len));
+        Ok(())
+    }
+
+    /// Rewrites the staged AppendVecs with the latest version of each account,
+    /// hashing the accounts kept for the manifest of the bank at `bank_slot`.
+    fn write_latest_versions(
+        &self,
+        state: RepackState,
+        bank_slot: Slot,
+        summary: &mut RepackSummary,
+    ) -> io::Result<ManifestAccounts> {
+        let RepackState {
+            mut staged,
+            mut versions,
+        } = state;
+        staged.sort_unstable();
+        let mut storages: HashMap<Slot, Vec<SerializableAccountStorageEntry>> = HashMap::new();
+        let mut hashes = Vec::new();
+        let mut delta_hashes = Vec::new();
+        let (mut capitalization, mut accounts_data_len) = (0u64, 0u64);
+        for (slot, id, len) in staged {
+            let name = format!("{slot}.{id}");
+            let staged_path = self.staging_dir.join(STAGED_DIR).join(&name);
+            let path = self.staging_dir.join(ACCOUNTS_DIR).join(&name);
+            let append_vec = AppendVec::new_from_file(
+                &staged_path,
+                len,
+                slot,
+                id,
+                Some(AccountsFileFormat::AppendVec),
+            )?;
+            let mut writer = AccountsWriter::create(&path)?;
+            for handle in append_vec_iter(&append_vec) {
+                let Some(account) = handle.access() else {
+                    continue;
+                };
+                let write_version = account.meta.write_version_obsolete;
+                let pubkey = account.meta.pubkey;
+                if versions.take_latest(&pubkey, slot, write_version).is_some() {
+                    writer.append(&append_vec.account_bytes(&account))?;
+                    let hash = AccountsDb::hash_account(&account, &pubkey);
+                    if slot == bank_slot {
+                        delta_hashes.push((pubkey, hash));
+                    }
+                    hashes.push((pubkey, hash));
+                    capitalization += account.account_meta.lamports;
+                    accounts_data_len += account.data.len() as u64;
+                }
+            }
+            let (len, accounts) = writer.finish()?;
+            drop(append_vec);
+            fs::remove_file(staged_path)?;
+
+            if accounts == 0 {
+                fs::remove_file(path)?;
+                continue;
+            }
+            storages
+                .entry(slot)
+                .or_default()
+                .push(SerializableAccountStorageEntry {
+                    id: id as usize,
+                    accounts_current_len: len,
+                });
+            summary.append_vecs += 1;
+            summary.accounts += accounts;
+            summary.account_bytes += len as u64;
+        }
+        Ok(ManifestAccounts {
+            storages,
+            capitalization,
+            accounts_data_len,
+            accounts_delta_hash: AccountsHasher::accumulate_account_hashes(delta_hashes),
+            accounts_hash: AccountsHasher::accumulate_account_hashes(hashes),
+        })
+    }
+
+    /// Writes the snapshot archive with the manifest of `extractor` rehashed for the
+    /// filtered AppendVecs, see [`crate::manifest::SnapshotManifest::with_accounts`].
+    ///
+    /// If `out` is a directory, the archive is written to it under the name validators
+    /// expect, `snapshot-<slot>-<hash>.tar.zst`.
+    pub fn finish<E: SnapshotExtractor>(
+        self,
+        extractor: &E,
+        out: &Path,
+    ) -> SnapshotResult<RepackSummary> {
+        let slot = extractor.slot();
+        let mut summary = RepackSummary {
+            slot,
+            ..RepackSummary::default()
+        };
+        let state = std::mem::take(&mut *self.state.lock().unwrap());
+        let accounts = self
+            .write_latest_versions(state, slot, &mut summary)
+            .write_context(&self.staging_dir)?;
+        let mut append_vecs: Vec<_> = accounts
+            .storages
+            .iter()
+            .flat_map(|(slot, entries)| entries.iter().map(move |entry| (*slot, entry.id)))
+            .collect();
+        append_vecs.sort_unstable();
+        summary.capitalization = accounts.capitalization;
+        let manifest = extractor.manifest().with_accounts(accounts)?;
+        summary.snapshot_hash = manifest.snapshot_hash().to_string();
+
+        let archive_name = format!("snapshot-{slot}-{}.tar.zst", summary.snapshot_hash);
+        let out = if out.is_dir() {
+            out.join(&archive_name)
+        } else {
+            if out.file_name().and_then(|name| name.to_str()) != Some(archive_name.as_str()) {
+                warn!("Validators only load {out:?} if it is renamed to {archive_name}");
+            }
+            out.to_path_buf()
+        };
+        let out = out.as_path();
+        summary.archive = out.to_path_buf();
+        let mut manifest_bytes = Vec::new();
+        manifest.write_to(&mut manifest_bytes).write_context(out)?;
+        let status_cache = extractor.status_cache()?;
+        let version = extractor.version().unwrap_or(DEFAULT_SNAPSHOT_VERSION);
+
+        let write_archive = || -> io::Result<()> {
+            let encoder = zstd::Encoder::new(BufWriter::new(File::create(out)?), 0)?;
+            let mut archive = tar::Builder::new(encoder);
+            append_file(&mut archive, VERSION_FILE, version.as_bytes())?;
+            append_dir(&mut archive, &format!("{SNAPSHOTS_DIR}/"))?;
+            append_dir(&mut archive, &format!("{SNAPSHOTS_DIR}/{slot}/"))?;
+            append_file(
+                &mut archive,
+                &format!("{SNAPSHOTS_DIR}/{slot}/{slot}"),
+                &manifest_bytes,
+            )?;
+            append_file(
+                &mut archive,
+                &format!("{SNAPSHOTS_DIR}/{SNAPSHOT_STATUS_CACHE_FILENAME}"),
+                &status_cache,
+            )?;
+            for (slot, id) in append_vecs {
+                let name = format!("{slot}.{id}");
+                let path = self.staging_dir.join(ACCOUNTS_DIR).join(&name);
+                archive.append_path_with_name(&path, format!("{ACCOUNTS_DIR}/{name}"))?;
+            }
+            archive.into_inner()?.finish()?.flush()
+        };
+        write_archive().write_context(out)?;
+        fs::remove_dir_all(&self.staging_dir).write_context(&self.staging_dir)?;
+        Ok(summary)
+    }
+}
+
+/// Writes accounts to an AppendVec file, aligning them to 8 bytes.
+struct AccountsWriter {
+    writer: BufWriter<File>,
+    len: usize,
+    accounts: u64,
+}
+
+impl AccountsWriter {
+    fn create(path: &Path) -> io::Result<Self> {
+        Ok(Self {
+            writer: BufWriter::new(File::create(path)?),
+            len: 0,
+            accounts: 0,
+        })
+    }
+
+    fn append(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let aligned_len = u64_align!(self.len);
+        self.writer.write_all(&[0u8; 8][..aligned_len - self.len])?;
+        self.writer.write_all(bytes)?;
+        self.len = aligned_len + bytes.len();
+        self.accounts += 1;
+        Ok(())
+    }
+
+    /// Returns the length of the AppendVec and its number of accounts.
+    fn finish(mut self) -> io::Result<(usize, u64)> {
+        self.writer.flush()?;
+        Ok((self.len, self.accounts))
+    }
+}
+
+fn append_file<W: Write>(archive: &mut tar::Builder<W>, path: &str, data: &[u8]) -> io::Result<()> {
+    let mut header = Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_entry_type(EntryType::Regular);
+    archive.append_data(&mut header, path, data)
+}
+
+fn append_dir<W: Write>(archive: &mut tar::Builder<W>, path: &str) -> io::Result<()> {
+    let mut header = Header::new_gnu();
+    header.set_size(0);
+    header.set_mode(0o755);
+    header.set_entry_type(EntryType::Directory);
+    archive.append_data(&mut header, path, io::empty())
+}
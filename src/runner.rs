@@ -0,0 +1,365 @@
+//! Command-line options and run bookkeeping shared by the `solana-snapshot-etl` subcommands
+//! and the sinks of other crates, see [`crate::sink`].
+
+use {
+    crate::{
+        accounts::{par_process_accounts, AccountConsumer, AccountOptions},
+        append_vec::AccountsFileFormat,
+        loader::SupportedLoader,
+        metrics::{metrics, serve_metrics},
+        parallel::{
+            par_process_append_vecs, AppendVecProcessor, DiscardOutput, OnError, OutputSink,
+            ParIterConfig, SkippedAppendVec, SnapshotInfo,
+        },
+        progress::AccountsProgress,
+        AppendVecIterator, SnapshotExtractor,
+    },
+    anyhow::Context,
+    indicatif::MultiProgress,
+    log::{error, info, warn},
+    serde::Serialize,
+    serde_with::{serde_as, DisplayFromStr},
+    solana_sdk::{clock::Slot, hash::Hash},
+    std::{
+        fs::File,
+        io::{BufWriter, Write},
+        net::SocketAddr,
+        path::{Path, PathBuf},
+        time::Instant,
+    },
+};
+
+/// Options of every subcommand: the snapshot source, parallelism, error handling and reporting.
+#[derive(Clone, Debug, clap::Args)]
+pub struct CommonArgs {
+    /// Snapshot source (unpacked snapshot, archive file, or HTTP link)
+    #[clap(long)]
+    pub source: String,
+
+    /// Number of threads used to process snapshot,
+    /// by default number of CPUs would be used.
+    #[clap(long)]
+    pub num_threads: Option<usize>,
+
+    /// Number of AppendVecs decoded in parallel, by default `--num-threads`
+    #[clap(long)]
+    pub decode_threads: Option<usize>,
+
+    /// Maximum number of AppendVecs read ahead of processing, by default twice `--num-threads`
+    #[clap(long)]
+    pub buffer_size: Option<usize>,
+
+    /// Process AppendVecs in the order of the source: by slot for snapshot directories,
+    /// in entry order for archives
+    #[clap(long)]
+    pub ordered: bool,
+
+    /// What to do with AppendVecs that can't be read: abort, or skip them after logging
+    #[clap(long, default_value = "abort")]
+    pub on_error: OnError,
+
+    /// Output JSON report of the AppendVecs skipped with `--on-error skip`
+    #[clap(long)]
+    pub error_report: Option<PathBuf>,
+
+    /// Serve Prometheus metrics at `http://<METRICS_ADDR>/metrics`
+    #[clap(long)]
+    pub metrics_addr: Option<SocketAddr>,
+
+    /// Output JSON summary of the run, written whether it succeeded or not
+    #[clap(long)]
+    pub summary_out: Option<PathBuf>,
+
+    /// Read every account storage file in this layout instead of detecting it:
+    /// append-vec, or hot for the tiered storage files of newer validators
+    #[clap(long)]
+    pub storage_format: Option<AccountsFileFormat>,
+}
+
+impl CommonArgs {
+    /// Reads `source` with the defaults of the command line.
+    pub const fn new(source: String) -> Self {
+        Self {
+            source,
+            num_threads: None,
+            decode_threads: None,
+            buffer_size: None,
+            ordered: false,
+            on_error: OnError::Abort,
+            error_report: None,
+            metrics_addr: None,
+            summary_out: None,
+            storage_format: None,
+        }
+    }
+
+    pub fn par_iter_config(&self) -> ParIterConfig {
+        let mut config = ParIterConfig::new(self.num_threads.unwrap_or_else(num_cpus::get));
+        config.decode_threads = self.decode_threads.unwrap_or(config.decode_threads);
+        config.buffer_size = self.buffer_size.unwrap_or(config.buffer_size);
+        config.ordered = self.ordered;
+        if self.ordered {
+            config.sink_threads = 1;
+        }
+        config.on_error = self.on_error;
+        config
+    }
+}
+
+/// Runs a subcommand with the [`CommonArgs`]: opens the source, shows progress, feeds AppendVecs
+/// to consumers and collects the skipped ones, then writes the error report and run summary.
+pub struct Runner {
+    args: CommonArgs,
+    config: ParIterConfig,
+    skipped: Vec<SkippedAppendVec>,
+    summary: RunSummary,
+    progress: MultiProgress,
+    accounts_progress: Option<AccountsProgress>,
+}
+
+impl Runner {
+    /// Starts a run of `sink`, the subcommand name reported in the run summary.
+    pub fn new(args: CommonArgs, sink: &str) -> Self {
+        Self {
+            config: args.par_iter_config(),
+            skipped: Vec::new(),
+            summary: RunSummary::new(&args.source, sink),
+            progress: MultiProgress::new(),
+            accounts_progress: None,
+            args,
+        }
+    }
+
+    pub const fn config(&self) -> ParIterConfig {
+        self.config
+    }
+
+    /// Labels the consumers of the run with `name` in the metrics, instead of the name of their type.
+    pub fn set_consumer_name(&mut self, name: &'static str) {
+        self.config.name = Some(name);
+    }
+
+    /// Serves metrics with `--metrics-addr`, opens the snapshot source and starts showing the progress
+    /// of accounts. Must be called within a multi-threaded Tokio runtime.
+    pub fn open(&mut self) -> anyhow::Result<(SupportedLoader, SnapshotInfo)> {
+        if let Some(addr) = self.args.metrics_addr {
+            let server = serve_metrics(addr)
+                .map_err(|err| anyhow::anyhow!("Failed to serve metrics at {addr}: {err}"))?;
+            tokio::spawn(async move {
+                if let Err(err) = server.await {
+                    warn!("Metrics server failed: {err}");
+                }
+            });
+            info!("Serving metrics at http://{addr}/metrics");
+        }
+
+        // Loaders block, and the HTTP client can't be created in an async context.
+        let loader = tokio::task::block_in_place(|| {
+            SupportedLoader::new(&self.args.source, self.args.storage_format, &self.progress)
+        })?;
+        let info = SnapshotInfo {
+            accounts_len: loader.accounts_len(),
+            ..SnapshotInfo::new(loader.manifest())
+        };
+        self.summary.slot = Some(info.slot);
+        self.summary.hash = Some(info.hash);
+        metrics().start(info.accounts_len);
+        self.accounts_progress = Some(AccountsProgress::new(&self.progress, info.accounts_len)?);
+        Ok((loader, info))
+    }
+
+    /// Processes AppendVecs with the configured parallelism and error policy, see [`par_process_append_vecs`].
+    /// Processors count the accounts they process in [`Metrics::accounts`](crate::metrics::Metrics::accounts).
+    pub async fn run<F, P>(
+        &mut self,
+        iterator: AppendVecIterator<'_>,
+        info: &SnapshotInfo,
+        create_processor: F,
+    ) -> anyhow::Result<Vec<P>>
+    where
+        F: Fn() -> P,
+        P: AppendVecProcessor<Output = ()> + Send + 'static,
+    {
+        let (processors, _) = self
+            .run_with_sink(iterator, info, create_processor, DiscardOutput)
+            .await?;
+        Ok(processors)
+    }
+
+    /// Runs processors whose outputs are consumed by `sink`.
+    pub async fn run_with_sink<F, P, S>(
+        &mut self,
+        iterator: AppendVecIterator<'_>,
+        info: &SnapshotInfo,
+        create_processor: F,
+        sink: S,
+    ) -> anyhow::Result<(Vec<P>, S)>
+    where
+        F: Fn() -> P,
+        P: AppendVecProcessor + Send + 'static,
+        P::Output: Send + 'static,
+        S: OutputSink<P::Output> + Send + 'static,
+    {
+        let (output, sink) =
+            par_process_append_vecs(iterator, info, create_processor, sink, self.config).await?;
+        self.skipped.extend(output.skipped);
+        Ok((output.consumers, sink))
+    }
+
+    /// Feeds accounts to account consumers, see [`par_process_accounts`]. Accounts not matching
+    /// the filter or dropped by a transform are reported as filtered in the run summary.
+    pub async fn run_accounts<F, C>(
+        &mut self,
+        iterator: AppendVecIterator<'_>,
+        info: &SnapshotInfo,
+        create_consumer: F,
+        options: &AccountOptions,
+    ) -> anyhow::Result<Vec<C>>
+    where
+        F: Fn() -> C,
+        C: AccountConsumer + Send + 'static,
+    {
+        let output =
+            par_process_accounts(iterator, info, create_consumer, options, self.config).await?;
+        self.skipped.extend(output.skipped);
+        if options.filter.is_some() || !options.transforms.is_empty() {
+            self.set_filtered_accounts(metrics().filtered_accounts.get());
+        }
+        Ok(output.consumers)
+    }
+
+    /// Sets the number of accounts processed but not written by the sink, for the run summary.
+    pub fn set_filtered_accounts(&mut self, accounts: u64) {
+        self.summary.filtered_accounts = Some(accounts);
+    }
+
+    /// Stops showing progress, then writes the error report and run summary of `result`.
+    /// Returns the error of `result` if any, else the first error writing the reports.
+    pub fn finish(mut self, result: anyhow::Result<()>) -> anyhow::Result<()> {
+        if let Some(accounts_progress) = self.accounts_progress.take() {
+            accounts_progress.finish();
+        }
+        if !self.skipped.is_empty() {
+            warn!("Skipped {} AppendVecs", self.skipped.len());
+        }
+        let mut write_results = Vec::new();
+        if let Some(error_report) = &self.args.error_report {
+            write_results.push(
+                write_json_file(error_report, &self.skipped)
+                    .with_context(|| format!("Failed to write error report {error_report:?}")),
+            );
+        }
+        if let Some(summary_out) = &self.args.summary_out {
+            self.summary.finish(&self.skipped, result.as_ref().err());
+            write_results.push(
+                write_json_file(summary_out, &self.summary)
+                    .with_context(|| format!("Failed to write run summary {summary_out:?}")),
+            );
+        }
+        let mut write_error = None;
+        for err in write_results.into_iter().filter_map(Result::err) {
+            error!("{err:#}");
+            write_error.get_or_insert(err);
+        }
+        result?;
+        if let Some(err) = write_error {
+            return Err(err);
+        }
+        info!("Done!");
+        Ok(())
+    }
+}
+
+fn write_json_file<T: Serialize>(path: &Path, value: &T) -> anyhow::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    serde_json::to_writer(&mut writer, value)?;
+    writeln!(writer)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Summary of a run written to `--summary-out`, for orchestration to check whether it succeeded.
+#[serde_as]
+#[derive(Serialize)]
+struct RunSummary {
+    success: bool,
+    /// Snapshot slot and bank hash, unset if the snapshot couldn't be loaded.
+    slot: Option<Slot>,
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    hash: Option<Hash>,
+    source: String,
+    /// Subcommand the accounts were processed with.
+    sink: String,
+    accounts: u64,
+    append_vecs: u64,
+    /// Accounts not written by the sink: for repack the accounts not kept, for decode the
+    /// accounts without a decoder and for compact the stale or closed versions dropped.
+    filtered_accounts: Option<u64>,
+    skipped_append_vecs: u64,
+    duration_secs: f64,
+    accounts_per_sec: f64,
+    append_vec_bytes_per_sec: f64,
+    /// Error that failed the run, and AppendVecs skipped with `--on-error skip`.
+    errors: Vec<String>,
+    #[serde(skip)]
+    start: Instant,
+}
+
+impl RunSummary {
+    fn new(source: &str, sink: &str) -> Self {
+        Self {
+            success: false,
+            slot: None,
+            hash: None,
+            source: source.to_owned(),
+            sink: sink.to_owned(),
+            accounts: 0,
+            append_vecs: 0,
+            filtered_accounts: None,
+            skipped_append_vecs: 0,
+            duration_secs: 0.0,
+            accounts_per_sec: 0.0,
+            append_vec_bytes_per_sec: 0.0,
+            errors: Vec::new(),
+            start: Instant::now(),
+        }
+    }
+
+    /// Fills in the counts from the metrics and the outcome of the run.
+    fn finish(&mut self, skipped: &[SkippedAppendVec], error: Option<&anyhow::Error>) {
+        let metrics = metrics();
+        let duration = self.start.elapsed().as_secs_f64();
+        self.success = error.is_none();
+        self.accounts = metrics.accounts.get();
+        self.append_vecs = metrics.append_vecs.get();
+        self.skipped_append_vecs = skipped.len() as u64;
+        self.duration_secs = duration;
+        if duration > 0.0 {
+            self.accounts_per_sec = self.accounts as f64 / duration;
+            self.append_vec_bytes_per_sec = metrics.append_vec_bytes.get() as f64 / duration;
+        }
+        self.errors = error
+            .map(error_message)
+            .into_iter()
+            .chain(skipped.iter().map(|skipped| {
+                format!(
+                    "Skipped AppendVec {}.{}: {}",
+                    skipped.slot, skipped.id, skipped.reason
+                )
+            }))
+            .collect();
+    }
+}
+
+/// Formats an error with its causes, skipping causes already included in the message.
+fn error_message(err: &anyhow::Error) -> String {
+    let mut message = err.to_string();
+    for cause in err.chain().skip(1) {
+        let cause = cause.to_string();
+        if !message.contains(&cause) {
+            message.push_str(": ");
+            message.push_str(&cause);
+        }
+    }
+    message
+}
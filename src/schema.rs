@@ -0,0 +1,314 @@
+//! Decoding of program accounts through a declarative JSON layout file.
+//!
+//! A schema describes the account types of one program. Accounts are matched by owner
+//! and data size, the first matching account type is used:
+//!
+//! ```json
+//! {
+//!   "program_id": "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA",
+//!   "accounts": [
+//!     {
+//!       "name": "Mint",
+//!       "data_size": 82,
+//!       "fields": [
+//!         { "name": "mint_authority", "type": { "coption": "pubkey" } },
+//!         { "name": "supply", "type": "u64", "offset": 36 },
+//!         { "name": "decimals", "type": "u8" },
+//!         { "name": "is_initialized", "type": "bool" },
+//!         { "name": "freeze_authority", "type": { "coption": "pubkey" } }
+//!       ]
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! Fields without `offset` directly follow the previous field. Supported types are
+//! the primitives `bool`, `u8`..`u128`, `i8`..`i128`, `f32`, `f64` and `pubkey`,
+//! fixed arrays (`{ "array": [type, len] }`), Borsh options with a 1-byte tag
+//! (`{ "option": type }`) and C-style options with a 4-byte tag that always store
+//! their value (`{ "coption": type }`). Array elements must not be zero-size.
+
+use {
+    crate::decode::{AccountDecoder, DataReader, DecodeResult, DecodedAccount, Primitive},
+    serde::{Deserialize, Deserializer},
+    serde_json::{Map, Value},
+    serde_with::{serde_as, DisplayFromStr},
+    solana_sdk::pubkey::Pubkey,
+    std::path::Path,
+    thiserror::Error,
+};
+
+#[derive(Error, Debug)]
+pub enum SchemaError {
+    #[error("{0}")]
+    IOError(#[from] std::io::Error),
+    #[error("Failed to parse schema: {0}")]
+    JsonError(#[from] serde_json::Error),
+    #[error("Invalid schema: {0}")]
+    Invalid(String),
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum FieldType {
+    Primitive(#[serde(deserialize_with = "deserialize_primitive")] Primitive),
+    Array { array: (Box<FieldType>, usize) },
+    Option { option: Box<FieldType> },
+    COption { coption: Box<FieldType> },
+}
+
+/// Size of a field type that doesn't fit in `usize`.
+struct SizeOverflow;
+
+impl FieldType {
+    /// Size of the type in bytes, `None` if it depends on the data.
+    fn size(&self) -> Result<Option<usize>, SizeOverflow> {
+        Ok(match self {
+            Self::Primitive(primitive) => Some(primitive.size()),
+            Self::Array { array: (ty, len) } => match ty.size()? {
+                Some(size) => Some(size.checked_mul(*len).ok_or(SizeOverflow)?),
+                None => None,
+            },
+            Self::Option { .. } => None,
+            Self::COption { coption } => match coption.size()? {
+                Some(size) => Some(size.checked_add(4).ok_or(SizeOverflow)?),
+                None => None,
+            },
+        })
+    }
+
+    /// Whether the type holds an array of zero-size elements, which would decode its
+    /// whole declared length without reading any data.
+    fn has_zero_size_array(&self) -> bool {
+        match self {
+            Self::Primitive(_) => false,
+            Self::Array { array: (ty, _) } => {
+                matches!(ty.size(), Ok(Some(0))) || ty.has_zero_size_array()
+            }
+            Self::Option { option: ty } | Self::COption { coption: ty } => ty.has_zero_size_array(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FieldLayout {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub ty: FieldType,
+    /// Offset from the start of the account data, follows the previous field if not set.
+    pub offset: Option<usize>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AccountLayout {
+    pub name: String,
+    /// Exact data size of matching accounts, any size matches if not set.
+    pub data_size: Option<usize>,
+    pub fields: Vec<FieldLayout>,
+}
+
+#[serde_as]
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Schema {
+    #[serde_as(as = "DisplayFromStr")]
+    pub program_id: Pubkey,
+    pub accounts: Vec<AccountLayout>,
+}
+
+impl Schema {
+    fn validate(&self) -> Result<(), SchemaError> {
+        for account in &self.accounts {
+            if let Some(field) = account
+                .fields
+                .iter()
+                .find(|field| field.ty.has_zero_size_array())
+            {
+                return Err(SchemaError::Invalid(format!(
+                    "field {}.{} is an array of zero-size elements",
+                    account.name, field.name
+                )));
+            }
+            let Some(data_size) = account.data_size else {
+                continue;
+            };
+            let mut end = Some(0usize);
+            for field in &account.fields {
+                let overflow = || {
+                    SchemaError::Invalid(format!(
+                        "field {}.{} ends beyond the addressable size",
+                        account.name, field.name
+                    ))
+                };
+                let start = field.offset.or(end);
+                let size = field.ty.size().map_err(|SizeOverflow| overflow())?;
+                end = match start.zip(size) {
+                    Some((start, size)) => Some(start.checked_add(size).ok_or_else(overflow)?),
+                    None => None,
+                };
+                if let Some(end) = end.filter(|end| *end > data_size) {
+                    return Err(SchemaError::Invalid(format!(
+                        "field {}.{} ends at {end}, beyond data size {data_size}",
+                        account.name, field.name
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Decodes program accounts according to a [`Schema`].
+pub struct SchemaDecoder {
+    schema: Schema,
+}
+
+impl SchemaDecoder {
+    pub fn new(schema: Schema) -> Result<Self, SchemaError> {
+        schema.validate()?;
+        Ok(Self { schema })
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, SchemaError> {
+        Self::new(serde_json::from_reader(std::fs::File::open(path)?)?)
+    }
+
+    fn decode_account(layout: &AccountLayout, data: &[u8]) -> DecodeResult<Value> {
+        let mut reader = DataReader::new(data, 0);
+        let mut map = Map::new();
+        for field in &layout.fields {
+            if let Some(offset) = field.offset {
+                reader = DataReader::new(data, offset);
+            }
+            map.insert(
+                field.name.clone(),
+                Self::decode_type(&field.ty, &mut reader)?,
+            );
+        }
+        Ok(map.into())
+    }
+
+    fn decode_type(ty: &FieldType, reader: &mut DataReader) -> DecodeResult<Value> {
+        Ok(match ty {
+            FieldType::Primitive(primitive) => reader.read_primitive(*primitive)?,
+            FieldType::Array { array: (ty, len) } => (0..*len)
+                .map(|_| Self::decode_type(ty, reader))
+                .collect::<DecodeResult<Vec<_>>>()?
+                .into(),
+            FieldType::Option { option } => {
+                if reader.read_bool()? {
+                    Self::decode_type(option, reader)?
+                } else {
+                    Value::Null
+                }
+            }
+            FieldType::COption { coption } => {
+                let some = reader.read_coption_tag()?;
+                let value = Self::decode_type(coption, reader)?;
+                if some {
+                    value
+                } else {
+                    Value::Null
+                }
+            }
+        })
+    }
+}
+
+impl AccountDecoder for SchemaDecoder {
+    fn program_id(&self) -> Pubkey {
+        self.schema.program_id
+    }
+
+    fn decode(&self, data: &[u8]) -> Option<DecodeResult<DecodedAccount>> {
+        let layout = self.schema.accounts.iter().find(|layout| {
+            layout
+                .data_size
+                .map(|size| size == data.len())
+                .unwrap_or(true)
+        })?;
+        Some(
+            Self::decode_account(layout, data).map(|value| DecodedAccount {
+                type_name: layout.name.clone(),
+                value,
+            }),
+        )
+    }
+}
+
+fn deserialize_primitive<'de, D>(deserializer: D) -> Result<Primitive, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let name = String::deserialize(deserializer)?;
+    Primitive::from_name(&name)
+        .ok_or_else(|| serde::de::Error::custom(format!("unknown primitive type {name}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, serde_json::json};
+
+    fn schema(accounts: Value) -> Result<SchemaDecoder, SchemaError> {
+        SchemaDecoder::new(
+            serde_json::from_value(json!({
+                "program_id": "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA",
+                "accounts": accounts,
+            }))
+            .unwrap(),
+        )
+    }
+
+    #[test]
+    fn decodes_fields_in_order_and_at_offsets() {
+        let decoder = schema(json!([{
+            "name": "Pair",
+            "data_size": 8,
+            "fields": [
+                { "name": "a", "type": "u16" },
+                { "name": "b", "type": { "array": ["u8", 2] } },
+                { "name": "c", "type": "u16", "offset": 6 },
+            ],
+        }]))
+        .unwrap();
+
+        let decoded = decoder.decode(&[1, 0, 2, 3, 0, 0, 4, 0]).unwrap().unwrap();
+        assert_eq!(decoded.type_name, "Pair");
+        assert_eq!(decoded.value, json!({ "a": 1, "b": [2, 3], "c": 4 }));
+        assert!(decoder.decode(&[0; 7]).is_none());
+    }
+
+    #[test]
+    fn rejects_offset_overflow() {
+        let result = schema(json!([{
+            "name": "Overflow",
+            "data_size": 8,
+            "fields": [{ "name": "a", "type": "u64", "offset": usize::MAX }],
+        }]));
+        assert!(matches!(result, Err(SchemaError::Invalid(_))));
+    }
+
+    #[test]
+    fn rejects_size_overflow() {
+        let result = schema(json!([{
+            "name": "Overflow",
+            "data_size": 8,
+            "fields": [{ "name": "a", "type": { "array": ["u64", usize::MAX] } }],
+        }]));
+        assert!(matches!(result, Err(SchemaError::Invalid(_))));
+    }
+
+    #[test]
+    fn rejects_zero_size_array_elements() {
+        let result = schema(json!([{
+            "name": "Empty",
+            "fields": [{
+                "name": "a",
+                "type": { "option": { "array": [{ "array": ["u8", 0] }, usize::MAX] } },
+            }],
+        }]));
+        assert!(matches!(result, Err(SchemaError::Invalid(_))));
+    }
+}
@@ -0,0 +1,136 @@
+//! Sinks of snapshot accounts defined outside of this crate, run with the options, progress reporting
+//! and error handling of the `solana-snapshot-etl` command line.
+//!
+//! A [`Sink`] parses its own command-line arguments and creates an [`AccountConsumer`] per worker.
+//! [`Pipeline`] assembles the source, filter and transforms of accounts with a sink, and [`run_cli`]
+//! runs a sink as a command-line tool.
+
+use {
+    crate::{
+        accounts::{Account, AccountConsumer, AccountFilter, AccountOptions},
+        parallel::SnapshotInfo,
+        runner::{CommonArgs, Runner},
+        SnapshotExtractor,
+    },
+    clap::{CommandFactory, FromArgMatches, Parser},
+    std::sync::Arc,
+};
+
+/// Accounts per batch delivered to the consumers of a [`Pipeline`], unless set.
+pub const DEFAULT_BATCH_SIZE: usize = 1024;
+
+/// Destination of the accounts of a snapshot.
+pub trait Sink: Sized {
+    /// Name of the sink in the run summary, and label of its consumers in the metrics.
+    const NAME: &'static str;
+
+    /// Arguments of the sink, parsed after the [`CommonArgs`] by [`run_cli`].
+    type Config: clap::Args;
+
+    /// Consumer of the accounts of a worker. Fallible setup, such as connecting, belongs in
+    /// [`AccountConsumer::on_start`].
+    type Consumer: AccountConsumer + Send + 'static;
+
+    /// Creates the sink once the snapshot is opened.
+    fn new(config: Self::Config, info: &SnapshotInfo) -> anyhow::Result<Self>;
+
+    fn create_consumer(&self) -> Self::Consumer;
+
+    /// Called with the finished consumers once all accounts were delivered, to merge their results.
+    fn finish(self, _consumers: Vec<Self::Consumer>) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// Runs a [`Sink`] over the accounts of a snapshot.
+///
+/// ```no_run
+/// # use solana_sdk::account::ReadableAccount;
+/// # use solana_snapshot_etl::{runner::CommonArgs, sink::{Pipeline, Sink}};
+/// # async fn run<S: Sink>(config: S::Config) -> anyhow::Result<()> {
+/// Pipeline::<S>::new(CommonArgs::new("snapshot.tar.zst".to_owned()), config)
+///     .transform(|account| (account.account.lamports() > 0).then_some(account))
+///     .batch_size(512)
+///     .run()
+///     .await
+/// # }
+/// ```
+pub struct Pipeline<S: Sink> {
+    args: CommonArgs,
+    config: S::Config,
+    options: AccountOptions,
+}
+
+impl<S: Sink> Pipeline<S> {
+    pub const fn new(args: CommonArgs, config: S::Config) -> Self {
+        Self {
+            args,
+            config,
+            options: AccountOptions::new(DEFAULT_BATCH_SIZE),
+        }
+    }
+
+    /// Only delivers the accounts matching `filter`.
+    pub fn filter(mut self, filter: AccountFilter) -> Self {
+        self.options.filter = Some(Arc::new(filter));
+        self
+    }
+
+    /// Maps accounts before they are delivered, or drops them by returning `None`.
+    /// Transforms are applied in the order they were added.
+    pub fn transform<T>(mut self, transform: T) -> Self
+    where
+        T: Fn(Account) -> Option<Account> + Send + Sync + 'static,
+    {
+        self.options.transforms.push(Arc::new(transform));
+        self
+    }
+
+    pub const fn batch_size(mut self, batch_size: usize) -> Self {
+        self.options.batch_size = batch_size;
+        self
+    }
+
+    /// Opens the source and delivers its accounts to the sink, then writes the error report and
+    /// run summary of the [`CommonArgs`]. Must be called within a multi-threaded Tokio runtime.
+    pub async fn run(self) -> anyhow::Result<()> {
+        let mut runner = Runner::new(self.args, S::NAME);
+        runner.set_consumer_name(S::NAME);
+        let result = Self::run_sink(&mut runner, self.config, &self.options).await;
+        runner.finish(result)
+    }
+
+    async fn run_sink(
+        runner: &mut Runner,
+        config: S::Config,
+        options: &AccountOptions,
+    ) -> anyhow::Result<()> {
+        let (mut loader, info) = runner.open()?;
+        let sink = S::new(config, &info)?;
+        let consumers = runner
+            .run_accounts(loader.iter(), &info, || sink.create_consumer(), options)
+            .await?;
+        tokio::task::block_in_place(|| sink.finish(consumers))
+    }
+}
+
+/// Command line of a sink: the [`CommonArgs`] and the arguments of the sink.
+#[derive(Debug, Parser)]
+struct SinkArgs<C: clap::Args> {
+    #[command(flatten)]
+    common: CommonArgs,
+
+    #[command(flatten)]
+    config: C,
+}
+
+/// Runs a sink as a command-line tool named after it, with the options of `solana-snapshot-etl`
+/// followed by those of the sink. Logs at the info level unless set with `RUST_LOG`.
+pub async fn run_cli<S: Sink>() -> anyhow::Result<()> {
+    let _ = env_logger::try_init_from_env(
+        env_logger::Env::default().filter_or(env_logger::DEFAULT_FILTER_ENV, "info"),
+    );
+    let matches = SinkArgs::<S::Config>::command().name(S::NAME).get_matches();
+    let args = SinkArgs::<S::Config>::from_arg_matches(&matches)?;
+    Pipeline::<S>::new(args.common, args.config).run().await
+}
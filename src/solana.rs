@@ -17,13 +17,15 @@
 use bincode::Options;
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
 use solana_accounts_db::account_storage::meta::StoredMetaWriteVersion;
 use solana_accounts_db::accounts_db::stats::BankHashStats;
 use solana_accounts_db::ancestors::AncestorsForSerialization;
 use solana_accounts_db::blockhash_queue::BlockhashQueue;
 use solana_frozen_abi_macro::AbiExample;
-use solana_runtime::epoch_stakes::EpochStakes;
-use solana_runtime::stakes::Stakes;
+use solana_runtime::epoch_stakes::{EpochStakes, VersionedEpochStakes};
+use solana_runtime::serde_snapshot::BankIncrementalSnapshotPersistence;
+use solana_runtime::stake_history::StakeHistory;
 use solana_sdk::clock::{Epoch, UnixTimestamp};
 use solana_sdk::deserialize_utils::default_on_eof;
 use solana_sdk::epoch_schedule::EpochSchedule;
@@ -35,6 +37,7 @@ use solana_sdk::pubkey::Pubkey;
 use solana_sdk::rent_collector::RentCollector;
 use solana_sdk::slot_history::Slot;
 use solana_sdk::stake::state::Delegation;
+use solana_vote::vote_account::VoteAccounts;
 use std::collections::{HashMap, HashSet};
 use std::io::Read;
 
@@ -90,13 +93,25 @@ pub struct DeserializableVersionedBank {
     pub rent_collector: RentCollector,
     pub epoch_schedule: EpochSchedule,
     pub inflation: Inflation,
-    pub stakes: Stakes<Delegation>,
+    pub stakes: DeserializableStakes,
     #[allow(dead_code)]
     unused_accounts: UnusedAccounts,
     pub epoch_stakes: HashMap<Epoch, EpochStakes>,
     pub is_delta: bool,
 }
 
+/// Mirror of `solana_runtime::stakes::Stakes<Delegation>`, which keeps its
+/// stake delegations private.
+#[derive(Clone, Default, Debug, Deserialize)]
+pub struct DeserializableStakes {
+    pub vote_accounts: VoteAccounts,
+    pub stake_delegations: HashMap<Pubkey, Delegation>,
+    #[allow(dead_code)]
+    unused: u64,
+    pub epoch: Epoch,
+    pub stake_history: StakeHistory,
+}
+
 #[derive(Clone, Default, Debug, Serialize, Deserialize, PartialEq, Eq, AbiExample)]
 pub struct BankHashInfo {
     pub hash: Hash,
@@ -104,7 +119,7 @@ pub struct BankHashInfo {
     pub stats: BankHashStats,
 }
 
-#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
 pub struct AccountsDbFields<T>(
     pub HashMap<Slot, Vec<T>>,
     pub StoredMetaWriteVersion,
@@ -120,8 +135,34 @@ pub struct AccountsDbFields<T>(
 
 pub type SerializedAppendVecId = usize;
 
-#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Deserialize)]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
 pub struct SerializableAccountStorageEntry {
     pub id: SerializedAppendVecId,
     pub accounts_current_len: usize,
 }
+
+/// Number of elements of an accounts lattice hash.
+const ACCOUNTS_LT_HASH_NUM_ELEMENTS: usize = 1024;
+
+/// Mirror of `solana_runtime::serde_snapshot::types::SerdeAccountsLtHash`, which is private.
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SerdeAccountsLtHash(
+    #[serde_as(as = "[_; ACCOUNTS_LT_HASH_NUM_ELEMENTS]")] pub [u16; ACCOUNTS_LT_HASH_NUM_ELEMENTS],
+);
+
+/// Fields appended to the manifest after the accounts DB fields by newer validators.
+/// Fields missing at the end of the manifest take their default value.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct ExtraFields {
+    #[serde(deserialize_with = "default_on_eof")]
+    pub lamports_per_signature: u64,
+    #[serde(deserialize_with = "default_on_eof")]
+    pub incremental_snapshot_persistence: Option<BankIncrementalSnapshotPersistence>,
+    #[serde(deserialize_with = "default_on_eof")]
+    pub epoch_accounts_hash: Option<Hash>,
+    #[serde(deserialize_with = "default_on_eof")]
+    pub versioned_epoch_stakes: HashMap<u64, VersionedEpochStakes>,
+    #[serde(deserialize_with = "default_on_eof")]
+    pub accounts_lt_hash: Option<SerdeAccountsLtHash>,
+}
@@ -0,0 +1,362 @@
+//! Decoding of stake and vote accounts and reconciliation against the
+//! stakes cache stored in the bank fields of the snapshot manifest.
+
+use {
+    crate::{
+        accounts::{Account, AccountFilter},
+        solana::DeserializableStakes,
+        versions::LatestVersions,
+    },
+    serde::Serialize,
+    serde_with::{serde_as, DisplayFromStr},
+    solana_sdk::{
+        account::ReadableAccount,
+        clock::{Epoch, Slot, UnixTimestamp},
+        pubkey::Pubkey,
+        stake::{
+            self,
+            state::{Delegation, StakeStateV2},
+        },
+        vote::{
+            self,
+            state::{VoteState, VoteStateVersions},
+        },
+    },
+    std::collections::HashMap,
+};
+
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct DelegationInfo {
+    #[serde_as(as = "DisplayFromStr")]
+    pub voter: Pubkey,
+    pub stake: u64,
+    pub activation_epoch: Epoch,
+    pub deactivation_epoch: Epoch,
+}
+
+impl From<&Delegation> for DelegationInfo {
+    fn from(delegation: &Delegation) -> Self {
+        Self {
+            voter: delegation.voter_pubkey,
+            stake: delegation.stake,
+            activation_epoch: delegation.activation_epoch,
+            deactivation_epoch: delegation.deactivation_epoch,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StakeAccountState {
+    Uninitialized,
+    Initialized,
+    Stake,
+    RewardsPool,
+}
+
+#[serde_as]
+#[derive(Clone, Debug, Serialize)]
+pub struct StakeAccountInfo {
+    #[serde_as(as = "DisplayFromStr")]
+    pub pubkey: Pubkey,
+    pub lamports: u64,
+    pub state: StakeAccountState,
+    pub rent_exempt_reserve: Option<u64>,
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    pub staker: Option<Pubkey>,
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    pub withdrawer: Option<Pubkey>,
+    pub lockup_unix_timestamp: Option<UnixTimestamp>,
+    pub lockup_epoch: Option<Epoch>,
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    pub lockup_custodian: Option<Pubkey>,
+    pub delegation: Option<DelegationInfo>,
+    pub credits_observed: Option<u64>,
+}
+
+impl StakeAccountInfo {
+    pub fn decode(pubkey: Pubkey, lamports: u64, data: &[u8]) -> Result<Self, String> {
+        let state: StakeStateV2 = bincode::deserialize(data).map_err(|e| e.to_string())?;
+        let mut info = Self {
+            pubkey,
+            lamports,
+            state: StakeAccountState::Uninitialized,
+            rent_exempt_reserve: None,
+            staker: None,
+            withdrawer: None,
+            lockup_unix_timestamp: None,
+            lockup_epoch: None,
+            lockup_custodian: None,
+            delegation: None,
+            credits_observed: None,
+        };
+        let meta = match &state {
+            StakeStateV2::Uninitialized => None,
+            StakeStateV2::Initialized(meta) => {
+                info.state = StakeAccountState::Initialized;
+                Some(meta)
+            }
+            StakeStateV2::Stake(meta, stake, _flags) => {
+                info.state = StakeAccountState::Stake;
+                info.delegation = Some(DelegationInfo::from(&stake.delegation));
+                info.credits_observed = Some(stake.credits_observed);
+                Some(meta)
+            }
+            StakeStateV2::RewardsPool => {
+                info.state = StakeAccountState::RewardsPool;
+                None
+            }
+        };
+        if let Some(meta) = meta {
+            info.rent_exempt_reserve = Some(meta.rent_exempt_reserve);
+            info.staker = Some(meta.authorized.staker);
+            info.withdrawer = Some(meta.authorized.withdrawer);
+            info.lockup_unix_timestamp = Some(meta.lockup.unix_timestamp);
+            info.lockup_epoch = Some(meta.lockup.epoch);
+            info.lockup_custodian = Some(meta.lockup.custodian);
+        }
+        Ok(info)
+    }
+}
+
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct VoteAccountInfo {
+    #[serde_as(as = "DisplayFromStr")]
+    pub pubkey: Pubkey,
+    pub lamports: u64,
+    #[serde_as(as = "DisplayFromStr")]
+    pub node_pubkey: Pubkey,
+    #[serde_as(as = "DisplayFromStr")]
+    pub authorized_withdrawer: Pubkey,
+    pub commission: u8,
+    pub credits: u64,
+    pub root_slot: Option<Slot>,
+}
+
+impl VoteAccountInfo {
+    pub fn decode(pubkey: Pubkey, lamports: u64, data: &[u8]) -> Result<Self, String> {
+        let vote_state = VoteState::deserialize(data).map_err(|e| e.to_string())?;
+        Ok(Self::from_vote_state(pubkey, lamports, &vote_state))
+    }
+
+    pub fn from_vote_state(pubkey: Pubkey, lamports: u64, vote_state: &VoteState) -> Self {
+        Self {
+            pubkey,
+            lamports,
+            node_pubkey: vote_state.node_pubkey,
+            authorized_withdrawer: vote_state.authorized_withdrawer,
+            commission: vote_state.commission,
+            credits: vote_state.credits(),
+            root_slot: vote_state.root_slot,
+        }
+    }
+}
+
+#[serde_as]
+#[derive(Clone, Debug, Serialize)]
+pub struct UndecodableAccount {
+    #[serde_as(as = "DisplayFromStr")]
+    pub pubkey: Pubkey,
+    #[serde_as(as = "DisplayFromStr")]
+    pub owner: Pubkey,
+    pub error: String,
+}
+
+/// Difference between the stake and vote accounts found in AppendVecs
+/// and the bank's stakes cache.
+#[serde_as]
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum StakesDiscrepancy {
+    /// Delegation is cached, but no delegated stake account was stored.
+    StakeMissingFromAccounts {
+        #[serde_as(as = "DisplayFromStr")]
+        pubkey: Pubkey,
+    },
+    /// Delegated stake account was stored, but is not cached.
+    StakeMissingFromCache {
+        #[serde_as(as = "DisplayFromStr")]
+        pubkey: Pubkey,
+    },
+    DelegationMismatch {
+        #[serde_as(as = "DisplayFromStr")]
+        pubkey: Pubkey,
+        cached: DelegationInfo,
+        stored: DelegationInfo,
+    },
+    VoteMissingFromAccounts {
+        #[serde_as(as = "DisplayFromStr")]
+        pubkey: Pubkey,
+    },
+    VoteMissingFromCache {
+        #[serde_as(as = "DisplayFromStr")]
+        pubkey: Pubkey,
+    },
+    VoteAccountMismatch {
+        #[serde_as(as = "DisplayFromStr")]
+        pubkey: Pubkey,
+        cached: VoteAccountInfo,
+        stored: VoteAccountInfo,
+    },
+}
+
+#[derive(Debug, Serialize)]
+pub struct StakesReport {
+    pub epoch: Epoch,
+    pub stake_accounts: Vec<StakeAccountInfo>,
+    pub vote_accounts: Vec<VoteAccountInfo>,
+    pub undecodable: Vec<UndecodableAccount>,
+    pub discrepancies: Vec<StakesDiscrepancy>,
+}
+
+enum DecodedAccount {
+    Stake(StakeAccountInfo),
+    Vote(VoteAccountInfo),
+    /// Vote account that was never initialized.
+    Ignored,
+    Undecodable(UndecodableAccount),
+}
+
+impl DecodedAccount {
+    fn new(pubkey: Pubkey, owner: Pubkey, lamports: u64, data: &[u8]) -> Self {
+        let decoded = if owner == stake::program::ID {
+            StakeAccountInfo::decode(pubkey, lamports, data).map(Self::Stake)
+        } else if !VoteStateVersions::is_correct_size_and_initialized(data) {
+            Ok(Self::Ignored)
+        } else {
+            VoteAccountInfo::decode(pubkey, lamports, data).map(Self::Vote)
+        };
+        decoded.unwrap_or_else(|error| {
+            Self::Undecodable(UndecodableAccount {
+                pubkey,
+                owner,
+                error,
+            })
+        })
+    }
+}
+
+/// Collects the latest version of every stake and vote account.
+///
+/// Closed accounts of any owner are kept as tombstones, as the closing version of a stake
+/// or vote account is owned by the system program.
+#[derive(Default)]
+pub struct StakesCollector {
+    accounts: LatestVersions<DecodedAccount>,
+}
+
+impl StakesCollector {
+    /// Selects the accounts to process: stake and vote accounts, and closed accounts.
+    pub fn account_filter() -> AccountFilter {
+        AccountFilter::new([], [stake::program::ID, vote::program::ID]).with_closed_accounts()
+    }
+
+    pub fn process_account(&mut self, account: &Account) {
+        let owner = *account.account.owner();
+        let lamports = account.account.lamports();
+        if lamports != 0 && owner != stake::program::ID && owner != vote::program::ID {
+            return;
+        }
+        let Account {
+            slot,
+            write_version,
+            pubkey,
+            ..
+        } = *account;
+        if !self.accounts.is_newer(&pubkey, slot, write_version) {
+            return;
+        }
+
+        let decoded = (lamports != 0)
+            .then(|| DecodedAccount::new(pubkey, owner, lamports, account.account.data()));
+        self.accounts.insert(pubkey, slot, write_version, decoded);
+    }
+
+    pub fn merge(&mut self, other: StakesCollector) {
+        self.accounts.merge(other.accounts);
+    }
+
+    /// Cross-checks the collected accounts against the bank's stakes cache.
+    pub fn reconcile(self, stakes: &DeserializableStakes) -> StakesReport {
+        let mut stake_accounts = HashMap::new();
+        let mut vote_accounts = HashMap::new();
+        let mut undecodable = Vec::new();
+        for (pubkey, account) in self.accounts.into_values() {
+            match account {
+                DecodedAccount::Stake(info) => {
+                    stake_accounts.insert(pubkey, info);
+                }
+                DecodedAccount::Vote(info) => {
+                    vote_accounts.insert(pubkey, info);
+                }
+                DecodedAccount::Ignored => {}
+                DecodedAccount::Undecodable(account) => undecodable.push(account),
+            }
+        }
+
+        let mut discrepancies = Vec::new();
+        for (pubkey, delegation) in &stakes.stake_delegations {
+            let stored = stake_accounts
+                .get(pubkey)
+                .and_then(|info| info.delegation.as_ref());
+            let cached = DelegationInfo::from(delegation);
+            match stored {
+                None => discrepancies
+                    .push(StakesDiscrepancy::StakeMissingFromAccounts { pubkey: *pubkey }),
+                Some(stored) if *stored != cached => {
+                    discrepancies.push(StakesDiscrepancy::DelegationMismatch {
+                        pubkey: *pubkey,
+                        cached,
+                        stored: stored.clone(),
+                    })
+                }
+                Some(_) => {}
+            }
+        }
+        for (pubkey, info) in &stake_accounts {
+            if info.delegation.is_some() && !stakes.stake_delegations.contains_key(pubkey) {
+                discrepancies.push(StakesDiscrepancy::StakeMissingFromCache { pubkey: *pubkey });
+            }
+        }
+        for (pubkey, vote_account) in stakes.vote_accounts.iter() {
+            let cached = VoteAccountInfo::from_vote_state(
+                *pubkey,
+                vote_account.lamports(),
+                vote_account.vote_state(),
+            );
+            match vote_accounts.get(pubkey) {
+                None => discrepancies
+                    .push(StakesDiscrepancy::VoteMissingFromAccounts { pubkey: *pubkey }),
+                Some(stored) if *stored != cached => {
+                    discrepancies.push(StakesDiscrepancy::VoteAccountMismatch {
+                        pubkey: *pubkey,
+                        cached,
+                        stored: stored.clone(),
+                    })
+                }
+                Some(_) => {}
+            }
+        }
+        for pubkey in vote_accounts.keys() {
+            if stakes.vote_accounts.get(pubkey).is_none() {
+                discrepancies.push(StakesDiscrepancy::VoteMissingFromCache { pubkey: *pubkey });
+            }
+        }
+
+        let mut stake_accounts: Vec<_> = stake_accounts.into_values().collect();
+        stake_accounts.sort_unstable_by_key(|info| info.pubkey);
+        let mut vote_accounts: Vec<_> = vote_accounts.into_values().collect();
+        vote_accounts.sort_unstable_by_key(|info| info.pubkey);
+        undecodable.sort_unstable_by_key(|account| account.pubkey);
+
+        StakesReport {
+            epoch: stakes.epoch,
+            stake_accounts,
+            vote_accounts,
+            undecodable,
+            discrepancies,
+        }
+    }
+}
@@ -0,0 +1,324 @@
+//! Aggregate statistics over the accounts and AppendVecs of a snapshot.
+
+use {
+    crate::{append_vec::AppendVec, append_vec_iter},
+    serde::Serialize,
+    serde_with::{serde_as, DisplayFromStr},
+    solana_sdk::{clock::Slot, pubkey::Pubkey},
+    std::{
+        collections::{HashMap, HashSet},
+        fmt,
+    },
+};
+
+/// Number of owners shown in the table output.
+const TABLE_OWNERS: usize = 20;
+
+/// Histogram with power-of-two buckets. Bucket 0 counts zeros,
+/// bucket `i` counts values in `[2^(i-1), 2^i)`.
+#[derive(Clone, Debug)]
+struct Log2Histogram {
+    buckets: [u64; 65],
+}
+
+impl Default for Log2Histogram {
+    fn default() -> Self {
+        Self { buckets: [0; 65] }
+    }
+}
+
+impl Log2Histogram {
+    fn add(&mut self, value: u64) {
+        self.buckets[(u64::BITS - value.leading_zeros()) as usize] += 1;
+    }
+
+    fn merge(&mut self, other: &Log2Histogram) {
+        for (bucket, count) in self.buckets.iter_mut().zip(other.buckets.iter()) {
+            *bucket += count;
+        }
+    }
+
+    fn to_buckets(&self) -> Vec<HistogramBucket> {
+        self.buckets
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(i, count)| HistogramBucket {
+                min: if i == 0 { 0 } else { 1 << (i - 1) },
+                max: if i == 0 { 0 } else { u64::MAX >> (64 - i) },
+                count: *count,
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct HistogramBucket {
+    /// Smallest value of the bucket, inclusive.
+    pub min: u64,
+    /// Largest value of the bucket, inclusive.
+    pub max: u64,
+    pub count: u64,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct OwnerTotals {
+    accounts: u64,
+    data_bytes: u64,
+}
+
+#[serde_as]
+#[derive(Clone, Debug, Serialize)]
+pub struct OwnerStats {
+    #[serde_as(as = "DisplayFromStr")]
+    pub owner: Pubkey,
+    pub accounts: u64,
+    pub data_bytes: u64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct StaleStats {
+    pub unique_accounts: u64,
+    /// Stored account versions superseded by a newer version of the same account.
+    pub stale_versions: u64,
+    /// Share of all stored account versions that are stale.
+    pub stale_share: f64,
+}
+
+/// Statistics report of a snapshot.
+#[derive(Clone, Debug, Serialize)]
+pub struct SnapshotStats {
+    pub slot: Slot,
+    pub accounts: u64,
+    pub data_bytes: u64,
+    pub zero_lamport_accounts: u64,
+    pub executable_accounts: u64,
+    pub append_vecs: u64,
+    pub min_append_vec_slot: Option<Slot>,
+    pub max_append_vec_slot: Option<Slot>,
+    /// Sum of `accounts_current_len` of all AppendVecs.
+    pub used_bytes: u64,
+    /// Sum of AppendVec file sizes.
+    pub capacity_bytes: u64,
+    /// Bytes of AppendVec files past `accounts_current_len`.
+    pub dead_bytes: u64,
+    /// Only present if stale versions were counted.
+    pub stale: Option<StaleStats>,
+    /// Owner programs, by descending number of accounts.
+    pub owners: Vec<OwnerStats>,
+    pub data_size_histogram: Vec<HistogramBucket>,
+    pub lamports_histogram: Vec<HistogramBucket>,
+}
+
+/// Collects statistics over AppendVecs. Collectors of parallel workers can be merged.
+#[derive(Default)]
+pub struct StatsCollector {
+    accounts: u64,
+    data_bytes: u64,
+    zero_lamport_accounts: u64,
+    executable_accounts: u64,
+    append_vecs: u64,
+    min_append_vec_slot: Option<Slot>,
+    max_append_vec_slot: Option<Slot>,
+    used_bytes: u64,
+    capacity_bytes: u64,
+    owners: HashMap<Pubkey, OwnerTotals>,
+    data_size_histogram: Log2Histogram,
+    lamports_histogram: Log2Histogram,
+    /// Set of all pubkeys, only tracked when counting stale versions.
+    unique_pubkeys: Option<HashSet<Pubkey>>,
+}
+
+impl StatsCollector {
+    /// Counting stale versions requires keeping every pubkey in memory.
+    pub fn new(count_stale: bool) -> Self {
+        Self {
+            unique_pubkeys: count_stale.then(HashSet::new),
+            ..Self::default()
+        }
+    }
+
+    /// Number of accounts processed so far.
+    pub const fn accounts(&self) -> u64 {
+        self.accounts
+    }
+
+    pub fn process_append_vec(&mut self, append_vec: &AppendVec) {
+        self.append_vecs += 1;
+        self.used_bytes += append_vec.len() as u64;
+        self.capacity_bytes += append_vec.capacity();
+        let slot = append_vec.slot();
+        self.min_append_vec_slot = Some(self.min_append_vec_slot.map_or(slot, |s| s.min(slot)));
+        self.max_append_vec_slot = Some(self.max_append_vec_slot.map_or(slot, |s| s.max(slot)));
+
+        for handle in append_vec_iter(append_vec) {
+            let Some(account) = handle.access() else {
+                continue;
+            };
+            let lamports = account.account_meta.lamports;
+            let data_len = account.data.len() as u64;
+            self.accounts += 1;
+            self.data_bytes += data_len;
+            if lamports == 0 {
+                self.zero_lamport_accounts += 1;
+            }
+            if account.account_meta.executable {
+                self.executable_accounts += 1;
+            }
+            let owner = self.owners.entry(account.account_meta.owner).or_default();
+            owner.accounts += 1;
+            owner.data_bytes += data_len;
+            self.data_size_histogram.add(data_len);
+            self.lamports_histogram.add(lamports);
+            if let Some(unique_pubkeys) = &mut self.unique_pubkeys {
+                unique_pubkeys.insert(account.meta.pubkey);
+            }
+        }
+    }
+
+    pub fn merge(&mut self, other: StatsCollector) {
+        self.accounts += other.accounts;
+        self.data_bytes += other.data_bytes;
+        self.zero_lamport_accounts += other.zero_lamport_accounts;
+        self.executable_accounts += other.executable_accounts;
+        self.append_vecs += other.append_vecs;
+        self.min_append_vec_slot = self
+            .min_append_vec_slot
+            .into_iter()
+            .chain(other.min_append_vec_slot)
+            .min();
+        self.max_append_vec_slot = self
+            .max_append_vec_slot
+            .into_iter()
+            .chain(other.max_append_vec_slot)
+            .max();
+        self.used_bytes += other.used_bytes;
+        self.capacity_bytes += other.capacity_bytes;
+        for (owner, totals) in other.owners {
+            let entry = self.owners.entry(owner).or_default();
+            entry.accounts += totals.accounts;
+            entry.data_bytes += totals.data_bytes;
+        }
+        self.data_size_histogram.merge(&other.data_size_histogram);
+        self.lamports_histogram.merge(&other.lamports_histogram);
+        if let (Some(unique_pubkeys), Some(other)) =
+            (&mut self.unique_pubkeys, other.unique_pubkeys)
+        {
+            unique_pubkeys.extend(other);
+        }
+    }
+
+    pub fn finish(self, slot: Slot) -> SnapshotStats {
+        let mut owners: Vec<_> = self
+            .owners
+            .into_iter()
+            .map(|(owner, totals)| OwnerStats {
+                owner,
+                accounts: totals.accounts,
+                data_bytes: totals.data_bytes,
+            })
+            .collect();
+        owners.sort_unstable_by(|a, b| b.accounts.cmp(&a.accounts).then(a.owner.cmp(&b.owner)));
+
+        let stale = self.unique_pubkeys.map(|unique_pubkeys| {
+            let unique_accounts = unique_pubkeys.len() as u64;
+            let stale_versions = self.accounts - unique_accounts;
+            StaleStats {
+                unique_accounts,
+                stale_versions,
+                stale_share: if self.accounts == 0 {
+                    0.0
+                } else {
+                    stale_versions as f64 / self.accounts as f64
+                },
+            }
+        });
+
+        SnapshotStats {
+            slot,
+            accounts: self.accounts,
+            data_bytes: self.data_bytes,
+            zero_lamport_accounts: self.zero_lamport_accounts,
+            executable_accounts: self.executable_accounts,
+            append_vecs: self.append_vecs,
+            min_append_vec_slot: self.min_append_vec_slot,
+            max_append_vec_slot: self.max_append_vec_slot,
+            used_bytes: self.used_bytes,
+            capacity_bytes: self.capacity_bytes,
+            dead_bytes: self.capacity_bytes - self.used_bytes,
+            stale,
+            owners,
+            data_size_histogram: self.data_size_histogram.to_buckets(),
+            lamports_histogram: self.lamports_histogram.to_buckets(),
+        }
+    }
+}
+
+impl fmt::Display for SnapshotStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let slot_range = match (self.min_append_vec_slot, self.max_append_vec_slot) {
+            (Some(min), Some(max)) => format!("{min}..={max}"),
+            _ => "-".to_owned(),
+        };
+        writeln!(f, "{:<28} {:>20}", "Snapshot slot", self.slot)?;
+        writeln!(f, "{:<28} {:>20}", "Accounts", self.accounts)?;
+        writeln!(f, "{:<28} {:>20}", "Data bytes", self.data_bytes)?;
+        writeln!(
+            f,
+            "{:<28} {:>20}",
+            "Zero-lamport accounts", self.zero_lamport_accounts
+        )?;
+        writeln!(
+            f,
+            "{:<28} {:>20}",
+            "Executable accounts", self.executable_accounts
+        )?;
+        writeln!(f, "{:<28} {:>20}", "AppendVecs", self.append_vecs)?;
+        writeln!(f, "{:<28} {:>20}", "AppendVec slots", slot_range)?;
+        writeln!(f, "{:<28} {:>20}", "Used bytes", self.used_bytes)?;
+        writeln!(f, "{:<28} {:>20}", "Capacity bytes", self.capacity_bytes)?;
+        writeln!(f, "{:<28} {:>20}", "Dead bytes", self.dead_bytes)?;
+        if let Some(stale) = &self.stale {
+            writeln!(f, "{:<28} {:>20}", "Unique accounts", stale.unique_accounts)?;
+            writeln!(f, "{:<28} {:>20}", "Stale versions", stale.stale_versions)?;
+            writeln!(
+                f,
+                "{:<28} {:>19.2}%",
+                "Stale share",
+                stale.stale_share * 100.0
+            )?;
+        }
+
+        writeln!(f)?;
+        writeln!(f, "{:<44} {:>14} {:>18}", "Owner", "Accounts", "Data bytes")?;
+        for owner in self.owners.iter().take(TABLE_OWNERS) {
+            writeln!(
+                f,
+                "{:<44} {:>14} {:>18}",
+                owner.owner.to_string(),
+                owner.accounts,
+                owner.data_bytes
+            )?;
+        }
+        if self.owners.len() > TABLE_OWNERS {
+            writeln!(f, "... {} more owners", self.owners.len() - TABLE_OWNERS)?;
+        }
+
+        for (title, buckets) in [
+            ("Data size", &self.data_size_histogram),
+            ("Lamports", &self.lamports_histogram),
+        ] {
+            writeln!(f)?;
+            writeln!(f, "{:<44} {:>14}", title, "Accounts")?;
+            for bucket in buckets {
+                writeln!(
+                    f,
+                    "{:<44} {:>14}",
+                    format!("{}..={}", bucket.min, bucket.max),
+                    bucket.count
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
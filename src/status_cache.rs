@@ -0,0 +1,147 @@
+//! Status cache of a snapshot: the statuses of the transactions of recent slots.
+//!
+//! The status cache is keyed by recent blockhash. For each blockhash it stores 20-byte
+//! slices of the signatures and message hashes of the transactions referencing it,
+//! taken at a per-blockhash key index, so that full signatures cannot be recovered.
+
+use {
+    crate::{solana::deserialize_from, SnapshotError, SnapshotResult},
+    serde::{Serialize, Serializer},
+    serde_with::{serde_as, DisplayFromStr},
+    solana_sdk::{clock::Slot, hash::Hash, signature::Signature, transaction::TransactionError},
+    std::collections::HashMap,
+};
+
+/// Length of the signature and message hash slices stored in the status cache.
+pub const CACHED_KEY_SIZE: usize = 20;
+
+pub type KeySlice = [u8; CACHED_KEY_SIZE];
+
+/// Mirror of `solana_runtime::status_cache::SlotDelta`, the serialized status cache
+/// entries of one slot. `Arc<Mutex<_>>` serializes as its contents.
+type SerdeSlotDelta = (
+    Slot,
+    bool,
+    HashMap<Hash, (usize, Vec<(KeySlice, Result<(), TransactionError>)>)>,
+);
+
+#[derive(Clone, Debug, Default)]
+pub struct StatusCache {
+    /// Slots of the status cache, ordered by slot.
+    pub slots: Vec<SlotStatuses>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SlotStatuses {
+    pub slot: Slot,
+    pub is_root: bool,
+    pub blockhashes: Vec<BlockhashStatuses>,
+}
+
+#[serde_as]
+#[derive(Clone, Debug, Serialize)]
+pub struct BlockhashStatuses {
+    #[serde_as(as = "DisplayFromStr")]
+    pub blockhash: Hash,
+    /// Offset of the cached slices in the signatures and message hashes.
+    pub key_index: usize,
+    pub statuses: Vec<TransactionStatus>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TransactionStatus {
+    /// Signature or message hash slice, hex-encoded in JSON.
+    #[serde(serialize_with = "serialize_hex")]
+    pub key: KeySlice,
+    /// Error of the transaction, `None` if it succeeded.
+    pub err: Option<TransactionError>,
+}
+
+/// Status of a transaction found in the status cache.
+#[serde_as]
+#[derive(Clone, Debug, Serialize)]
+pub struct SignatureStatus {
+    pub slot: Slot,
+    #[serde_as(as = "DisplayFromStr")]
+    pub blockhash: Hash,
+    pub err: Option<TransactionError>,
+}
+
+impl StatusCache {
+    pub fn from_bytes(data: &[u8]) -> SnapshotResult<Self> {
+        let slot_deltas: Vec<SerdeSlotDelta> =
+            deserialize_from(data).map_err(SnapshotError::StatusCacheError)?;
+        let mut slots: Vec<_> = slot_deltas
+            .into_iter()
+            .map(|(slot, is_root, statuses)| {
+                let mut blockhashes: Vec<_> = statuses
+                    .into_iter()
+                    .map(|(blockhash, (key_index, statuses))| BlockhashStatuses {
+                        blockhash,
+                        key_index,
+                        statuses: statuses
+                            .into_iter()
+                            .map(|(key, result)| TransactionStatus {
+                                key,
+                                err: result.err(),
+                            })
+                            .collect(),
+                    })
+                    .collect();
+                blockhashes.sort_unstable_by_key(|statuses| statuses.blockhash.to_bytes());
+                SlotStatuses {
+                    slot,
+                    is_root,
+                    blockhashes,
+                }
+            })
+            .collect();
+        slots.sort_unstable_by_key(|statuses| statuses.slot);
+        Ok(Self { slots })
+    }
+
+    /// Number of cached signature and message hash slices.
+    pub fn len(&self) -> usize {
+        self.slots
+            .iter()
+            .flat_map(|slot| &slot.blockhashes)
+            .map(|blockhash| blockhash.statuses.len())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Looks up the statuses of the transaction with `signature`. Since only slices of
+    /// signatures are cached, false positives are possible but unlikely.
+    pub fn find(&self, signature: &Signature) -> Vec<SignatureStatus> {
+        let signature = signature.as_ref();
+        let mut found = Vec::new();
+        for slot in &self.slots {
+            for blockhash in &slot.blockhashes {
+                let Some(key) =
+                    signature.get(blockhash.key_index..blockhash.key_index + CACHED_KEY_SIZE)
+                else {
+                    continue;
+                };
+                found.extend(
+                    blockhash
+                        .statuses
+                        .iter()
+                        .filter(|status| status.key == key)
+                        .map(|status| SignatureStatus {
+                            slot: slot.slot,
+                            blockhash: blockhash.blockhash,
+                            err: status.err.clone(),
+                        }),
+                );
+            }
+        }
+        found
+    }
+}
+
+fn serialize_hex<S: Serializer>(key: &KeySlice, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&const_hex::encode(key))
+}
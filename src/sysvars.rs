@@ -0,0 +1,240 @@
+//! Extraction of sysvar accounts into their typed forms.
+
+#![allow(deprecated)]
+
+use {
+    crate::{
+        accounts::{Account, AccountFilter},
+        append_vec_iter, verify_append_vec,
+        versions::LatestVersions,
+        SnapshotExtractor, SnapshotResult,
+    },
+    serde::Serialize,
+    serde_with::{serde_as, DisplayFromStr},
+    solana_sdk::{
+        account::ReadableAccount,
+        clock::{Clock, Epoch, Slot},
+        epoch_rewards::EpochRewards,
+        epoch_schedule::EpochSchedule,
+        hash::Hash,
+        pubkey::Pubkey,
+        rent::Rent,
+        slot_hashes::SlotHashes,
+        slot_history::{self, SlotHistory},
+        stake_history::{StakeHistory, StakeHistoryEntry},
+        sysvar::{
+            self, fees::Fees, last_restart_slot::LastRestartSlot,
+            recent_blockhashes::RecentBlockhashes,
+        },
+    },
+};
+
+/// Sysvars decoded into [`Sysvars`].
+const SYSVAR_IDS: [Pubkey; 10] = [
+    sysvar::clock::ID,
+    sysvar::rent::ID,
+    sysvar::epoch_schedule::ID,
+    sysvar::fees::ID,
+    sysvar::slot_hashes::ID,
+    sysvar::stake_history::ID,
+    sysvar::recent_blockhashes::ID,
+    sysvar::epoch_rewards::ID,
+    sysvar::last_restart_slot::ID,
+    sysvar::slot_history::ID,
+];
+
+#[serde_as]
+#[derive(Clone, Debug, Serialize)]
+pub struct RecentBlockhash {
+    #[serde_as(as = "DisplayFromStr")]
+    pub blockhash: Hash,
+    pub lamports_per_signature: u64,
+}
+
+#[serde_as]
+#[derive(Clone, Debug, Serialize)]
+pub struct EpochRewardsInfo {
+    pub distribution_starting_block_height: u64,
+    pub num_partitions: u64,
+    #[serde_as(as = "DisplayFromStr")]
+    pub parent_blockhash: Hash,
+    pub total_points: u128,
+    pub total_rewards: u64,
+    pub distributed_rewards: u64,
+    pub active: bool,
+}
+
+impl From<&EpochRewards> for EpochRewardsInfo {
+    fn from(epoch_rewards: &EpochRewards) -> Self {
+        Self {
+            distribution_starting_block_height: epoch_rewards.distribution_starting_block_height,
+            num_partitions: epoch_rewards.num_partitions,
+            parent_blockhash: epoch_rewards.parent_blockhash,
+            total_points: epoch_rewards.total_points,
+            total_rewards: epoch_rewards.total_rewards,
+            distributed_rewards: epoch_rewards.distributed_rewards,
+            active: epoch_rewards.active,
+        }
+    }
+}
+
+/// Summary of the `SlotHistory` bitvector, which is too large to output as is.
+#[derive(Clone, Debug, Serialize)]
+pub struct SlotHistoryInfo {
+    pub next_slot: Slot,
+    pub oldest: Slot,
+    pub newest: Slot,
+    /// Number of slots between `oldest` and `newest` that were rooted.
+    pub rooted_slots: u64,
+}
+
+impl From<&SlotHistory> for SlotHistoryInfo {
+    fn from(slot_history: &SlotHistory) -> Self {
+        let rooted_slots = (slot_history.oldest()..=slot_history.newest())
+            .filter(|slot| slot_history.check(*slot) == slot_history::Check::Found)
+            .count() as u64;
+        Self {
+            next_slot: slot_history.next_slot,
+            oldest: slot_history.oldest(),
+            newest: slot_history.newest(),
+            rooted_slots,
+        }
+    }
+}
+
+#[serde_as]
+#[derive(Clone, Debug, Serialize)]
+pub struct UndecodableSysvar {
+    #[serde_as(as = "DisplayFromStr")]
+    pub pubkey: Pubkey,
+    pub error: String,
+}
+
+/// Sysvars found in a snapshot. Missing sysvars are `None`.
+#[serde_as]
+#[derive(Debug, Default, Serialize)]
+pub struct Sysvars {
+    /// Slot of the snapshot the sysvars were extracted from.
+    pub slot: Slot,
+    pub clock: Option<Clock>,
+    pub rent: Option<Rent>,
+    pub epoch_schedule: Option<EpochSchedule>,
+    pub fees: Option<Fees>,
+    #[serde_as(as = "Option<Vec<(_, DisplayFromStr)>>")]
+    pub slot_hashes: Option<Vec<(Slot, Hash)>>,
+    pub stake_history: Option<Vec<(Epoch, StakeHistoryEntry)>>,
+    pub recent_blockhashes: Option<Vec<RecentBlockhash>>,
+    pub epoch_rewards: Option<EpochRewardsInfo>,
+    pub last_restart_slot: Option<LastRestartSlot>,
+    pub slot_history: Option<SlotHistoryInfo>,
+    pub undecodable: Vec<UndecodableSysvar>,
+}
+
+/// Collects the latest version of every sysvar account.
+///
+/// Versions are tracked by pubkey whatever their owner, so that a sysvar account that was
+/// closed or is no longer owned by the sysvar program is not reported with stale data.
+#[derive(Default)]
+pub struct SysvarCollector {
+    accounts: LatestVersions<Vec<u8>>,
+}
+
+impl SysvarCollector {
+    /// Selects the accounts to process, the sysvar accounts whatever their owner.
+    pub fn account_filter() -> AccountFilter {
+        AccountFilter::new(SYSVAR_IDS, [])
+    }
+
+    pub fn process_account(&mut self, account: &Account) {
+        let Account {
+            slot,
+            write_version,
+            pubkey,
+            ..
+        } = *account;
+        if !SYSVAR_IDS.contains(&pubkey) || !self.accounts.is_newer(&pubkey, slot, write_version) {
+            return;
+        }
+        let live = *account.account.owner() == sysvar::ID && account.account.lamports() != 0;
+        let data = live.then(|| account.account.data().to_vec());
+        self.accounts.insert(pubkey, slot, write_version, data);
+    }
+
+    pub fn merge(&mut self, other: SysvarCollector) {
+        self.accounts.merge(other.accounts);
+    }
+
+    /// Deserializes the collected sysvar accounts.
+    pub fn finish(self, slot: Slot) -> Sysvars {
+        let mut sysvars = Sysvars {
+            slot,
+            ..Sysvars::default()
+        };
+        for (pubkey, data) in self.accounts.into_values() {
+            if let Err(error) = sysvars.decode(&pubkey, &data) {
+                sysvars.undecodable.push(UndecodableSysvar {
+                    pubkey,
+                    error: error.to_string(),
+                });
+            }
+        }
+        sysvars
+    }
+}
+
+impl Sysvars {
+    fn decode(&mut self, pubkey: &Pubkey, data: &[u8]) -> bincode::Result<()> {
+        if *pubkey == sysvar::clock::ID {
+            self.clock = Some(bincode::deserialize(data)?);
+        } else if *pubkey == sysvar::rent::ID {
+            self.rent = Some(bincode::deserialize(data)?);
+        } else if *pubkey == sysvar::epoch_schedule::ID {
+            self.epoch_schedule = Some(bincode::deserialize(data)?);
+        } else if *pubkey == sysvar::fees::ID {
+            self.fees = Some(bincode::deserialize(data)?);
+        } else if *pubkey == sysvar::slot_hashes::ID {
+            let slot_hashes: SlotHashes = bincode::deserialize(data)?;
+            self.slot_hashes = Some(slot_hashes.to_vec());
+        } else if *pubkey == sysvar::stake_history::ID {
+            let stake_history: StakeHistory = bincode::deserialize(data)?;
+            self.stake_history = Some(stake_history.to_vec());
+        } else if *pubkey == sysvar::recent_blockhashes::ID {
+            let recent_blockhashes: RecentBlockhashes = bincode::deserialize(data)?;
+            self.recent_blockhashes = Some(
+                recent_blockhashes
+                    .iter()
+                    .map(|entry| RecentBlockhash {
+                        blockhash: entry.blockhash,
+                        lamports_per_signature: entry.fee_calculator.lamports_per_signature,
+                    })
+                    .collect(),
+            );
+        } else if *pubkey == sysvar::epoch_rewards::ID {
+            let epoch_rewards: EpochRewards = bincode::deserialize(data)?;
+            self.epoch_rewards = Some(EpochRewardsInfo::from(&epoch_rewards));
+        } else if *pubkey == sysvar::last_restart_slot::ID {
+            self.last_restart_slot = Some(bincode::deserialize(data)?);
+        } else if *pubkey == sysvar::slot_history::ID {
+            let slot_history: SlotHistory = bincode::deserialize(data)?;
+            self.slot_history = Some(SlotHistoryInfo::from(&slot_history));
+        }
+        Ok(())
+    }
+}
+
+/// Iterates over all AppendVecs of a snapshot and returns its sysvars.
+pub fn extract_sysvars<E: SnapshotExtractor>(extractor: &mut E) -> SnapshotResult<Sysvars> {
+    let mut collector = SysvarCollector::default();
+    for append_vec in extractor.iter() {
+        let append_vec = append_vec?;
+        verify_append_vec(&append_vec)?;
+        for handle in append_vec_iter(&append_vec) {
+            if let Some(account) = handle.access() {
+                if SYSVAR_IDS.contains(&account.meta.pubkey) {
+                    collector.process_account(&Account::new(append_vec.slot(), &account));
+                }
+            }
+        }
+    }
+    Ok(collector.finish(extractor.slot()))
+}
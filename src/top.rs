@@ -0,0 +1,222 @@
+//! Top-N ranking of accounts by lamports, data size or token amount.
+
+use {
+    crate::append_vec::StoredAccountMeta,
+    serde::Serialize,
+    serde_with::{serde_as, DisplayFromStr},
+    solana_sdk::{clock::Slot, pubkey, pubkey::Pubkey},
+    std::{
+        cmp::{Ordering, Reverse},
+        collections::{hash_map::Entry, BinaryHeap, HashMap},
+        str::FromStr,
+    },
+};
+
+const TOKEN_PROGRAM_ID: Pubkey = pubkey!("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA");
+const TOKEN_2022_PROGRAM_ID: Pubkey = pubkey!("TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb");
+
+/// Size of an SPL token account without extensions.
+const TOKEN_ACCOUNT_LEN: usize = 165;
+/// Token-2022 account type byte following the base account, marking a token account.
+const TOKEN_2022_ACCOUNT_TYPE: u8 = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RankBy {
+    Lamports,
+    DataLen,
+    TokenAmount,
+}
+
+impl FromStr for RankBy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lamports" => Ok(Self::Lamports),
+            "data_len" => Ok(Self::DataLen),
+            "token_amount" => Ok(Self::TokenAmount),
+            _ => Err(format!(
+                "unknown ranking {s}, expected lamports, data_len or token_amount"
+            )),
+        }
+    }
+}
+
+#[serde_as]
+#[derive(Clone, Debug, Serialize)]
+pub struct TopAccount {
+    #[serde_as(as = "DisplayFromStr")]
+    pub pubkey: Pubkey,
+    #[serde_as(as = "DisplayFromStr")]
+    pub owner: Pubkey,
+    pub lamports: u64,
+    pub data_len: u64,
+    /// Token mint and amount, token accounts only.
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    pub mint: Option<Pubkey>,
+    pub token_amount: Option<u64>,
+    pub slot: Slot,
+    pub write_version: u64,
+}
+
+struct Ranked {
+    key: u64,
+    account: TopAccount,
+}
+
+impl Ranked {
+    const fn sort_key(&self) -> (u64, &Pubkey, Slot, u64) {
+        (
+            self.key,
+            &self.account.pubkey,
+            self.account.slot,
+            self.account.write_version,
+        )
+    }
+}
+
+impl PartialEq for Ranked {
+    fn eq(&self, other: &Self) -> bool {
+        self.sort_key() == other.sort_key()
+    }
+}
+
+impl Eq for Ranked {}
+
+impl PartialOrd for Ranked {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Ranked {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.sort_key().cmp(&other.sort_key())
+    }
+}
+
+/// Keeps the `limit` largest accounts seen, using a bounded min-heap.
+/// Collectors of parallel workers can be merged.
+///
+/// Rankings are computed over stored account versions rather than the latest version of every
+/// account, which would need memory for all accounts of the snapshot. Versions with zero lamports
+/// are closed and not ranked, and only the latest of the ranked versions of an account is reported.
+/// A stale version may still be reported if the newer version of the account did not rank,
+/// e.g. because it was closed, has a smaller key or no longer matches the filters.
+pub struct TopCollector {
+    rank_by: RankBy,
+    limit: usize,
+    owner: Option<Pubkey>,
+    mint: Option<Pubkey>,
+    heap: BinaryHeap<Reverse<Ranked>>,
+}
+
+impl TopCollector {
+    /// Only accounts owned by `owner`, and token accounts of `mint` are ranked, if set.
+    pub fn new(rank_by: RankBy, limit: usize, owner: Option<Pubkey>, mint: Option<Pubkey>) -> Self {
+        Self {
+            rank_by,
+            limit,
+            owner,
+            mint,
+            heap: BinaryHeap::with_capacity(limit + 1),
+        }
+    }
+
+    pub fn process_account(&mut self, slot: Slot, account: &StoredAccountMeta) {
+        let owner = account.account_meta.owner;
+        let lamports = account.account_meta.lamports;
+        if lamports == 0 || self.owner.is_some_and(|filter| filter != owner) {
+            return;
+        }
+        let token = parse_token_account(&owner, account.data);
+        if self.mint.is_some() && token.map(|(mint, _)| mint) != self.mint {
+            return;
+        }
+        let data_len = account.data.len() as u64;
+        let key = match self.rank_by {
+            RankBy::Lamports => lamports,
+            RankBy::DataLen => data_len,
+            RankBy::TokenAmount => match token {
+                Some((_, amount)) => amount,
+                None => return,
+            },
+        };
+        if self.heap.len() >= self.limit
+            && self
+                .heap
+                .peek()
+                .map_or(true, |Reverse(smallest)| key < smallest.key)
+        {
+            return;
+        }
+        self.push(Ranked {
+            key,
+            account: TopAccount {
+                pubkey: account.meta.pubkey,
+                owner,
+                lamports,
+                data_len,
+                mint: token.map(|(mint, _)| mint),
+                token_amount: token.map(|(_, amount)| amount),
+                slot,
+                write_version: account.meta.write_version_obsolete,
+            },
+        });
+    }
+
+    fn push(&mut self, ranked: Ranked) {
+        self.heap.push(Reverse(ranked));
+        if self.heap.len() > self.limit {
+            self.heap.pop();
+        }
+    }
+
+    pub fn merge(&mut self, other: TopCollector) {
+        for Reverse(ranked) in other.heap {
+            self.push(ranked);
+        }
+    }
+
+    /// Returns the ranked accounts, largest first. Only the latest version of each
+    /// account among the ranked versions is kept.
+    pub fn finish(self) -> Vec<TopAccount> {
+        let mut latest: HashMap<Pubkey, Ranked> = HashMap::new();
+        for Reverse(ranked) in self.heap {
+            match latest.entry(ranked.account.pubkey) {
+                Entry::Occupied(mut entry) => {
+                    let existing = &entry.get().account;
+                    if (existing.slot, existing.write_version)
+                        < (ranked.account.slot, ranked.account.write_version)
+                    {
+                        entry.insert(ranked);
+                    }
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(ranked);
+                }
+            }
+        }
+        let mut ranked: Vec<_> = latest.into_values().collect();
+        ranked.sort_unstable_by(|a, b| b.cmp(a));
+        ranked.into_iter().map(|ranked| ranked.account).collect()
+    }
+}
+
+/// Returns the mint and amount of an SPL Token or Token-2022 account.
+fn parse_token_account(owner: &Pubkey, data: &[u8]) -> Option<(Pubkey, u64)> {
+    let is_token_account = if *owner == TOKEN_PROGRAM_ID {
+        data.len() == TOKEN_ACCOUNT_LEN
+    } else if *owner == TOKEN_2022_PROGRAM_ID {
+        data.len() == TOKEN_ACCOUNT_LEN
+            || data.get(TOKEN_ACCOUNT_LEN) == Some(&TOKEN_2022_ACCOUNT_TYPE)
+    } else {
+        false
+    };
+    if !is_token_account {
+        return None;
+    }
+    let mint = Pubkey::try_from(&data[0..32]).ok()?;
+    let amount = u64::from_le_bytes(data[64..72].try_into().ok()?);
+    Some((mint, amount))
+}
@@ -0,0 +1,96 @@
+//! Unpacking of snapshot archives into snapshot directories.
+
+use {
+    crate::{
+        append_vec::AppendVec, unpacked::UnpackedSnapshotExtractor, NoopReadProgressTracking,
+        PathContext, SnapshotError, SnapshotExtractor, SnapshotResult, ACCOUNTS_DIR, SNAPSHOTS_DIR,
+        VERSION_FILE,
+    },
+    solana_runtime::snapshot_utils::SNAPSHOT_STATUS_CACHE_FILENAME,
+    std::{
+        fs::{self, File},
+        io::{self, BufWriter, Write},
+        path::{Path, PathBuf},
+    },
+};
+
+/// Writes a snapshot to a directory in the layout read by [`UnpackedSnapshotExtractor`].
+///
+/// AppendVecs can be written in parallel and are trimmed to their `accounts_current_len`.
+pub struct SnapshotUnpacker {
+    out_dir: PathBuf,
+}
+
+impl SnapshotUnpacker {
+    /// Creates the snapshot directory and writes the manifest, status cache and
+    /// version file of `extractor`.
+    pub fn new<E: SnapshotExtractor>(out_dir: &Path, extractor: &E) -> SnapshotResult<Self> {
+        write_snapshot_metadata(out_dir, extractor)?;
+        let accounts_dir = out_dir.join(ACCOUNTS_DIR);
+        fs::create_dir_all(&accounts_dir).write_context(&accounts_dir)?;
+        Ok(Self {
+            out_dir: out_dir.to_path_buf(),
+        })
+    }
+
+    pub fn write_append_vec(&self, append_vec: &AppendVec) -> io::Result<()> {
+        fs::write(
+            self.append_vec_path(append_vec.slot(), append_vec.id()),
+            append_vec.as_bytes(),
+        )
+    }
+
+    /// Checks that every AppendVec of the manifest was written, and opens the snapshot directory.
+    pub fn finish(self) -> SnapshotResult<UnpackedSnapshotExtractor> {
+        let extractor =
+            UnpackedSnapshotExtractor::open(&self.out_dir, Box::new(NoopReadProgressTracking {}))?;
+        for (slot, entries) in &extractor.manifest().accounts_db_fields.0 {
+            for entry in entries {
+                let path = self.append_vec_path(*slot, entry.id as u64);
+                let len = match fs::metadata(&path) {
+                    Ok(metadata) => metadata.len(),
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => 0,
+                    Err(err) => return Err(SnapshotError::FileError { path, source: err }),
+                };
+                if len < entry.accounts_current_len as u64 {
+                    return Err(SnapshotError::MissingAppendVec {
+                        slot: *slot,
+                        id: entry.id as u64,
+                    });
+                }
+            }
+        }
+        Ok(extractor)
+    }
+
+    fn append_vec_path(&self, slot: u64, id: u64) -> PathBuf {
+        self.out_dir.join(ACCOUNTS_DIR).join(format!("{slot}.{id}"))
+    }
+}
+
+/// Writes the manifest, status cache and version file of `extractor` to a snapshot directory.
+pub(crate) fn write_snapshot_metadata<E: SnapshotExtractor>(
+    out_dir: &Path,
+    extractor: &E,
+) -> SnapshotResult<()> {
+    let slot = extractor.slot();
+    let snapshot_dir = out_dir.join(SNAPSHOTS_DIR).join(slot.to_string());
+    fs::create_dir_all(&snapshot_dir).write_context(&snapshot_dir)?;
+
+    let manifest_path = snapshot_dir.join(slot.to_string());
+    let mut manifest = BufWriter::new(File::create(&manifest_path).write_context(&manifest_path)?);
+    extractor
+        .manifest()
+        .write_to(&mut manifest)
+        .and_then(|()| manifest.flush())
+        .write_context(&manifest_path)?;
+    let status_cache_path = out_dir
+        .join(SNAPSHOTS_DIR)
+        .join(SNAPSHOT_STATUS_CACHE_FILENAME);
+    fs::write(&status_cache_path, extractor.status_cache()?).write_context(&status_cache_path)?;
+    if let Some(version) = extractor.version() {
+        let version_path = out_dir.join(VERSION_FILE);
+        fs::write(&version_path, version).write_context(&version_path)?;
+    }
+    Ok(())
+}
@@ -1,34 +1,45 @@
 use {
     crate::{
-        deserialize_from, parse_append_vec_name, AccountsDbFields, AppendVec, AppendVecIterator,
-        DeserializableVersionedBank, ReadProgressTracking, SerializableAccountStorageEntry,
-        SnapshotError, SnapshotExtractor, SnapshotResult, SNAPSHOTS_DIR,
+        append_vec::AccountsFileFormat, manifest::SnapshotManifest, parse_append_vec_name,
+        AppendVec, AppendVecIterator, PathContext, ReadProgressTracking, SnapshotError,
+        SnapshotExtractor, SnapshotResult, ACCOUNTS_DIR, SNAPSHOTS_DIR, VERSION_FILE,
     },
     itertools::Itertools,
     log::info,
     solana_runtime::snapshot_utils::SNAPSHOT_STATUS_CACHE_FILENAME,
     std::{
+        borrow::Cow,
         fs::OpenOptions,
-        io::BufReader,
+        io::Read,
         path::{Path, PathBuf},
         str::FromStr,
-        time::Instant,
     },
 };
 
 /// Extracts account data from snapshots that were unarchived to a file system.
 pub struct UnpackedSnapshotExtractor {
     root: PathBuf,
-    accounts_db_fields: AccountsDbFields<SerializableAccountStorageEntry>,
-    slot: u64,
+    manifest: SnapshotManifest,
+    version: Option<String>,
+    storage_format: Option<AccountsFileFormat>,
 }
 
 impl SnapshotExtractor for UnpackedSnapshotExtractor {
     fn iter(&mut self) -> AppendVecIterator<'_> {
         Box::new(self.unboxed_iter())
     }
-    fn slot(&self) -> u64 {
-        self.slot
+    fn manifest(&self) -> &SnapshotManifest {
+        &self.manifest
+    }
+    fn status_cache(&self) -> SnapshotResult<Cow<'_, [u8]>> {
+        let path = self
+            .root
+            .join(SNAPSHOTS_DIR)
+            .join(SNAPSHOT_STATUS_CACHE_FILENAME);
+        Ok(Cow::Owned(std::fs::read(&path).path_context(&path)?))
+    }
+    fn version(&self) -> Option<&str> {
+        self.version.as_deref()
     }
 }
 
@@ -43,7 +54,7 @@ impl UnpackedSnapshotExtractor {
             return Err(SnapshotError::NoStatusCache);
         }
 
-        let snapshot_files = snapshots_dir.read_dir()?;
+        let snapshot_files = snapshots_dir.read_dir().path_context(&snapshots_dir)?;
 
         let snapshot_file_path = snapshot_files
             .filter_map(|entry| entry.ok())
@@ -52,80 +63,91 @@ impl UnpackedSnapshotExtractor {
             .ok_or(SnapshotError::NoSnapshotManifest)?;
 
         info!("Opening snapshot manifest: {:?}", snapshot_file_path);
-        let snapshot_file = OpenOptions::new().read(true).open(&snapshot_file_path)?;
-        let snapshot_file_len = snapshot_file.metadata()?.len();
+        let snapshot_file = OpenOptions::new()
+            .read(true)
+            .open(&snapshot_file_path)
+            .path_context(&snapshot_file_path)?;
+        let snapshot_file_len = snapshot_file
+            .metadata()
+            .path_context(&snapshot_file_path)?
+            .len();
 
-        let snapshot_file = progress_tracking.new_read_progress_tracker(
+        let mut snapshot_file = progress_tracking.new_read_progress_tracker(
             &snapshot_file_path,
             Box::new(snapshot_file),
             snapshot_file_len,
         )?;
-        let mut snapshot_file = BufReader::new(snapshot_file);
-
-        let pre_unpack = Instant::now();
-        let versioned_bank: DeserializableVersionedBank = deserialize_from(&mut snapshot_file)?;
-        let slot = versioned_bank.slot;
-        drop(versioned_bank);
-        let versioned_bank_post_time = Instant::now();
-
-        let accounts_db_fields: AccountsDbFields<SerializableAccountStorageEntry> =
-            deserialize_from(&mut snapshot_file)?;
-        let accounts_db_fields_post_time = Instant::now();
-        drop(snapshot_file);
-
-        info!(
-            "Read bank fields in {:?}",
-            versioned_bank_post_time - pre_unpack
-        );
-        info!(
-            "Read accounts DB fields in {:?}",
-            accounts_db_fields_post_time - versioned_bank_post_time
-        );
+        let version_path = path.join(VERSION_FILE);
+        let version = if version_path.is_file() {
+            let version = std::fs::read_to_string(&version_path).path_context(&version_path)?;
+            Some(version.trim().to_owned())
+        } else {
+            None
+        };
+        let mut manifest = Vec::with_capacity(snapshot_file_len as usize);
+        snapshot_file
+            .read_to_end(&mut manifest)
+            .path_context(&snapshot_file_path)?;
+        let manifest = SnapshotManifest::from_bytes(manifest, version.as_deref())?;
 
         Ok(UnpackedSnapshotExtractor {
             root: path.to_path_buf(),
-            accounts_db_fields,
-            slot,
+            manifest,
+            version,
+            storage_format: None,
         })
     }
 
+    /// Reads the account storage files in `format`, instead of detecting the format of each file.
+    pub const fn with_storage_format(mut self, format: Option<AccountsFileFormat>) -> Self {
+        self.storage_format = format;
+        self
+    }
+
     pub fn unboxed_iter(&self) -> impl Iterator<Item = SnapshotResult<AppendVec>> + '_ {
         std::iter::once(self.iter_streams())
             .flatten_ok()
-            .flatten_ok()
+            .map(|append_vec| append_vec.and_then(|append_vec| append_vec))
     }
 
+    /// Iterates over the AppendVecs ordered by slot and id.
     fn iter_streams(&self) -> SnapshotResult<impl Iterator<Item = SnapshotResult<AppendVec>> + '_> {
-        let accounts_dir = self.root.join("accounts");
+        let accounts_dir = self.root.join(ACCOUNTS_DIR);
         Ok(accounts_dir
-            .read_dir()?
+            .read_dir()
+            .path_context(&accounts_dir)?
             .filter_map(|f| f.ok())
             .filter_map(|f| {
                 let name = f.file_name();
                 parse_append_vec_name(&f.file_name()).map(move |parsed| (parsed, name))
             })
+            .sorted_unstable_by_key(|(parsed, _)| *parsed)
             .map(move |((slot, version), name)| {
                 self.open_append_vec(slot, version, &accounts_dir.join(name))
             }))
     }
 
     fn open_append_vec(&self, slot: u64, id: u64, path: &Path) -> SnapshotResult<AppendVec> {
-        let known_vecs = self
-            .accounts_db_fields
-            .0
-            .get(&slot)
-            .map(|v| &v[..])
-            .unwrap_or(&[]);
-        let known_vec = known_vecs.iter().find(|entry| entry.id == (id as usize));
-        let known_vec = match known_vec {
-            None => return Err(SnapshotError::UnexpectedAppendVec),
-            Some(v) => v,
+        let Some(known_vec) = self.manifest.storage_entry(slot, id) else {
+            return Err(SnapshotError::UnknownAppendVec {
+                slot,
+                id,
+                path: path.to_path_buf(),
+            });
         };
 
-        Ok(AppendVec::new_from_file(
+        AppendVec::new_from_file(
             path,
             known_vec.accounts_current_len,
             slot,
-        )?)
+            id,
+            self.storage_format,
+        )
+        .map_err(|source| SnapshotError::InvalidAppendVec {
+            slot,
+            id,
+            path: path.to_path_buf(),
+            source,
+        })
     }
 }
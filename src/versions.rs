@@ -0,0 +1,108 @@
+//! Latest version of accounts stored in a snapshot.
+//!
+//! AppendVecs of a snapshot can hold several versions of an account, the current one
+//! has the highest slot and write version. accounts-db stores a closed account as the
+//! default account: zero lamports, the system program as owner and no data. A closed
+//! version must hide the older versions of an account whatever its owner, so collectors
+//! record it as a tombstone before filtering on the owner.
+
+use {
+    solana_sdk::{clock::Slot, pubkey::Pubkey},
+    std::collections::{hash_map::Entry, HashMap},
+};
+
+/// Version of an account, `None` if the account was closed or is not collected.
+struct Versioned<T> {
+    slot: Slot,
+    write_version: u64,
+    value: Option<T>,
+}
+
+impl<T> Versioned<T> {
+    const fn version(&self) -> (Slot, u64) {
+        (self.slot, self.write_version)
+    }
+}
+
+/// Keeps a value for the latest version of every account inserted.
+/// Collectors of parallel workers can be merged.
+pub struct LatestVersions<T> {
+    accounts: HashMap<Pubkey, Versioned<T>>,
+}
+
+impl<T> Default for LatestVersions<T> {
+    fn default() -> Self {
+        Self {
+            accounts: HashMap::new(),
+        }
+    }
+}
+
+impl<T> LatestVersions<T> {
+    /// Whether the version of `pubkey` at `slot` and `write_version` is newer than the one kept.
+    pub fn is_newer(&self, pubkey: &Pubkey, slot: Slot, write_version: u64) -> bool {
+        self.accounts
+            .get(pubkey)
+            .map_or(true, |existing| existing.version() < (slot, write_version))
+    }
+
+    /// Keeps `value` for `pubkey` if its version is newer than the one kept.
+    /// A `None` value is a tombstone, hiding the older versions.
+    pub fn insert(&mut self, pubkey: Pubkey, slot: Slot, write_version: u64, value: Option<T>) {
+        self.insert_versioned(
+            pubkey,
+            Versioned {
+                slot,
+                write_version,
+                value,
+            },
+        );
+    }
+
+    fn insert_versioned(&mut self, pubkey: Pubkey, versioned: Versioned<T>) {
+        match self.accounts.entry(pubkey) {
+            Entry::Occupied(mut entry) => {
+                if entry.get().version() < versioned.version() {
+                    entry.insert(versioned);
+                }
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(versioned);
+            }
+        }
+    }
+
+    pub fn merge(&mut self, other: LatestVersions<T>) {
+        for (pubkey, versioned) in other.accounts {
+            self.insert_versioned(pubkey, versioned);
+        }
+    }
+
+    /// Whether a version of `pubkey` was inserted, tombstones included.
+    pub fn contains(&self, pubkey: &Pubkey) -> bool {
+        self.accounts.contains_key(pubkey)
+    }
+
+    /// Value of the latest version of `pubkey`, `None` if it is a tombstone or unknown.
+    pub fn get(&self, pubkey: &Pubkey) -> Option<&T> {
+        self.accounts.get(pubkey)?.value.as_ref()
+    }
+
+    /// Removes and returns the value of `pubkey` if its latest version is the one at `slot`
+    /// and `write_version`.
+    pub fn take_latest(&mut self, pubkey: &Pubkey, slot: Slot, write_version: u64) -> Option<T> {
+        match self.accounts.entry(*pubkey) {
+            Entry::Occupied(entry) if entry.get().version() == (slot, write_version) => {
+                entry.remove().value
+            }
+            _ => None,
+        }
+    }
+
+    /// Values of the latest versions, skipping tombstones.
+    pub fn into_values(self) -> impl Iterator<Item = (Pubkey, T)> {
+        self.accounts
+            .into_iter()
+            .filter_map(|(pubkey, versioned)| Some((pubkey, versioned.value?)))
+    }
+}